@@ -1,13 +1,13 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Throughput};
 
 extern crate memory_manager;
-use memory_manager::memory_manager::MemoryManager;
+use memory_manager::memory_manager::{MemoryManager, FIRST_DATA_PAGE_INDEX};
 use memory_manager::pages::config_page::ConfigPage;
-use memory_manager::pages::free_list_page::FreeListPage;
+use memory_manager::pages::free_list_page::{FreeListPage, FreeListPageRef};
+use memory_manager::pages::from_slice::FromSlice;
 use memory_manager::pages::Page;
 use memory_manager::u48::U48;
 use rand::Rng;
-use std::any;
 use std::cell::RefCell;
 
 fn memory_manager_bench(c: &mut criterion::Criterion) {
@@ -39,11 +39,12 @@ fn memory_manager_bench(c: &mut criterion::Criterion) {
         &pages_ref,
         |b, data| {
             // Obtén un préstamo mutable de pages_ref
-            let mut pages_borrowed = data.borrow_mut();
+            let _pages_borrowed = data.borrow_mut();
             b.iter(|| {
-                for i in 0..random_slice.len() {
-                    let mut any_page: ConfigPage<'_> = mem.get_page_mut(random_slice[i]).unwrap();
-                    any_page.data[0] = any_page.data[3] + i as u8;
+                for (i, &page) in random_slice.iter().enumerate() {
+                    let mut guard = mem.get_page_mut::<ConfigPage>(page).unwrap();
+                    let mut any_page = guard.page_mut();
+                    any_page.data_mut()[0] = any_page.data()[3] + i as u8;
                 }
 
                 /*               for i in 0..random_slice.len() {
@@ -61,23 +62,33 @@ fn memory_manager_bench(c: &mut criterion::Criterion) {
     group.finish();
 }
 
+// Page ids stored in the free list chain starting at first_page
+fn read_free_list(memory: &MemoryManager, first_page: U48) -> Vec<u64> {
+    let mut pages = vec![];
+    let mut next = first_page.get();
+    while next != 0 {
+        let free_list_page = memory.get_page_ref::<FreeListPageRef>(next).unwrap();
+        pages.extend(free_list_page.get_recycled_pages_list().unwrap());
+        next = free_list_page.get_free_list_page_next();
+    }
+    pages
+}
+
+#[allow(dead_code)]
 fn memory_manager_read_free_list_points_bench(c: &mut criterion::Criterion) {
     let num_pages: u64 = 8000000u64;
     let memory: MemoryManager = MemoryManager::new("bench_2.bin", num_pages).unwrap();
 
-    // We need to initialize the free pages list
-    let mut free_list_page = memory.get_page_mut::<FreeListPage>(1).unwrap();
-
-    free_list_page
+    // We need to initialize the free pages list, it starts after the meta pages
+    let mut scratch = [0u8; 4096];
+    FreeListPage::from_slice(&mut scratch)
         .init_free_list_pages(&memory, num_pages)
         .unwrap();
 
     memory.flush().unwrap();
-    
-    let get_free_pages_list_ptr_len =
-        FreeListPage::get_free_pages_list_ptr(U48::from(1u64), &memory)
-            .unwrap()
-            .len();
+
+    let first_page = U48::try_from(FIRST_DATA_PAGE_INDEX).unwrap();
+    let get_free_pages_list_ptr_len = read_free_list(&memory, first_page).len();
     println!(
         "get_free_pages_list_ptr size: {:?}",
         get_free_pages_list_ptr_len
@@ -95,8 +106,7 @@ fn memory_manager_read_free_list_points_bench(c: &mut criterion::Criterion) {
 
     // Envuelve pages en un RefCell
     let pages_ref = RefCell::new(pages);
-    let throughput =
-        Throughput::Bytes(get_free_pages_list_ptr_len as u64 * std::mem::size_of::<u64>() as u64);
+    let throughput = Throughput::Bytes(get_free_pages_list_ptr_len as u64 * U48::BYTES as u64);
     let mut group = c.benchmark_group("memory_manager");
 
     group.throughput(throughput);
@@ -104,9 +114,9 @@ fn memory_manager_read_free_list_points_bench(c: &mut criterion::Criterion) {
     group.bench_with_input(
         BenchmarkId::new("memory_manager_read_free_list_points_bench", 1),
         &pages_ref,
-        |b, data| {
+        |b, _data| {
             b.iter(|| {
-                FreeListPage::get_free_pages_list_ptr(U48::from(1u64), &memory).unwrap();
+                read_free_list(&memory, first_page);
             })
         },
     );
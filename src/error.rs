@@ -0,0 +1,182 @@
+use std::fmt;
+use std::io;
+
+// Every failure reported by the library. Callers that only deal with io::Error can still
+// use `?`, see the From implementation at the end of the file.
+#[derive(Debug)]
+pub enum MemoryManagerError {
+    // The free pages are exhausted and the growth policy doesn't allow growing any more
+    OutOfPages {
+        total_allocated_pages: u64,
+        last_used_page: u64,
+    },
+    // The file (or its header, config page...) doesn't hold a valid database
+    Corrupted(String),
+    // The file header doesn't start with our magic number, it's not one of our files
+    BadMagic {
+        found: [u8; 8],
+    },
+    // The file was written with another (older, newer or unknown) on-disk format
+    UnsupportedFormatVersion {
+        version: u64,
+        supported: u64,
+    },
+    // The file uses features this build doesn't know about
+    UnsupportedFeatures {
+        flags: u64,
+    },
+    // The checksum stored in a metadata page doesn't match its contents
+    ChecksumMismatch {
+        page: u64,
+        stored: u32,
+        computed: u32,
+    },
+    // Asked for a version that is not stored in the config history
+    VersionOutOfRange {
+        version: u64,
+        max_version: u64,
+    },
+    // Another writer (or a reader, when opening for writing) holds the file lock
+    Locked {
+        pid: u32,
+    },
+    // The page is already handed out, exclusively when exclusive is true
+    Borrowed {
+        page: u64,
+        exclusive: bool,
+    },
+    // num_pages pages starting at first_page go past the end of the store
+    OutOfBounds {
+        first_page: u64,
+        num_pages: u64,
+    },
+    // Writing to a store or a memory manager opened read-only
+    ReadOnly(String),
+    // Bad arguments: page sizes, segment sizes...
+    InvalidInput(String),
+    // The value doesn't fit in a field of that many bytes (see u48)
+    Overflow {
+        value: u64,
+        bytes: usize,
+    },
+    Io(io::Error),
+}
+
+impl MemoryManagerError {
+    // The io::ErrorKind this error maps to
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            MemoryManagerError::OutOfPages { .. } => io::ErrorKind::Other,
+            MemoryManagerError::Corrupted(_) => io::ErrorKind::InvalidData,
+            MemoryManagerError::BadMagic { .. } => io::ErrorKind::InvalidData,
+            MemoryManagerError::UnsupportedFormatVersion { .. } => io::ErrorKind::InvalidData,
+            MemoryManagerError::UnsupportedFeatures { .. } => io::ErrorKind::InvalidData,
+            MemoryManagerError::ChecksumMismatch { .. } => io::ErrorKind::InvalidData,
+            MemoryManagerError::VersionOutOfRange { .. } => io::ErrorKind::InvalidInput,
+            MemoryManagerError::Locked { .. } => io::ErrorKind::WouldBlock,
+            MemoryManagerError::Borrowed { .. } => io::ErrorKind::Other,
+            MemoryManagerError::OutOfBounds { .. } => io::ErrorKind::InvalidInput,
+            MemoryManagerError::ReadOnly(_) => io::ErrorKind::PermissionDenied,
+            MemoryManagerError::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            MemoryManagerError::Overflow { .. } => io::ErrorKind::InvalidInput,
+            MemoryManagerError::Io(e) => e.kind(),
+        }
+    }
+}
+
+impl fmt::Display for MemoryManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryManagerError::OutOfPages {
+                total_allocated_pages,
+                last_used_page,
+            } => write!(
+                f,
+                "Error: not enough pages! total_allocated_pages: {}, last_used_page: {}",
+                total_allocated_pages, last_used_page
+            ),
+            MemoryManagerError::Corrupted(msg) => write!(f, "{}", msg),
+            MemoryManagerError::BadMagic { found } => {
+                write!(f, "Not a database file: bad magic number {:02x?}", found)
+            }
+            MemoryManagerError::UnsupportedFormatVersion { version, supported } => write!(
+                f,
+                "Unsupported format version {}: this build only reads version {}",
+                version, supported
+            ),
+            MemoryManagerError::UnsupportedFeatures { flags } => {
+                write!(f, "Unsupported feature flags: {:#x}", flags)
+            }
+            MemoryManagerError::ChecksumMismatch {
+                page,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Checksum mismatch in page {}: stored {:#010x}, computed {:#010x}",
+                page, stored, computed
+            ),
+            MemoryManagerError::VersionOutOfRange {
+                version,
+                max_version,
+            } => write!(
+                f,
+                "Error: version {} is out of range, max version: {}",
+                version, max_version
+            ),
+            MemoryManagerError::Locked { pid } => write!(f, "database locked by pid {}", pid),
+            MemoryManagerError::Borrowed { page, exclusive } => {
+                if *exclusive {
+                    write!(f, "Page {} is already mutably borrowed", page)
+                } else {
+                    write!(f, "Page {} is already borrowed", page)
+                }
+            }
+            MemoryManagerError::OutOfBounds {
+                first_page,
+                num_pages,
+            } => {
+                if *num_pages == 1 {
+                    write!(f, "Index {} is out of bounds", first_page)
+                } else {
+                    write!(
+                        f,
+                        "Range of {} pages starting at {} is out of bounds",
+                        num_pages, first_page
+                    )
+                }
+            }
+            MemoryManagerError::ReadOnly(msg) => write!(f, "{}", msg),
+            MemoryManagerError::InvalidInput(msg) => write!(f, "{}", msg),
+            MemoryManagerError::Overflow { value, bytes } => {
+                write!(f, "Value {} doesn't fit in {} bytes", value, bytes)
+            }
+            MemoryManagerError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MemoryManagerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MemoryManagerError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MemoryManagerError {
+    fn from(e: io::Error) -> Self {
+        MemoryManagerError::Io(e)
+    }
+}
+
+// Keeps the error as the inner error, so it can be recovered with io::Error::downcast
+impl From<MemoryManagerError> for io::Error {
+    fn from(e: MemoryManagerError) -> Self {
+        match e {
+            MemoryManagerError::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
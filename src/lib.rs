@@ -1,3 +1,10 @@
+pub mod error;
 pub mod logger;
 pub mod memory_manager;
 pub mod pages;
+pub mod storage;
+pub mod u48;
+
+// Used by page_layout!
+#[doc(hidden)]
+pub use paste;
@@ -14,3 +14,9 @@ static LOGGER: Lazy<Logger> = Lazy::new(|| {
 pub fn get_logger() -> &'static Logger {
     &LOGGER
 }
+
+// Child of parent tagged with the database file. Every store and memory manager logs through
+// one of these, so the records of several databases can be told apart.
+pub fn file_logger(parent: &Logger, filename: &str) -> Logger {
+    parent.new(o!("file" => filename.to_string()))
+}
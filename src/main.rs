@@ -1,12 +1,9 @@
-mod logger;
-mod memory_manager;
-mod pages;
-
 extern crate slog;
 extern crate slog_term;
 
-use crate::memory_manager::MemoryManager;
-use crate::pages::page_manager::PageManager;
+use memory_manager::logger;
+use memory_manager::memory_manager::MemoryManager;
+use memory_manager::pages::page_manager::PageManager;
 use slog::debug;
 
 fn main() -> Result<(), std::io::Error> {
@@ -21,16 +18,16 @@ fn main() -> Result<(), std::io::Error> {
     page_manager.recyle_pages(&mut pending_recycled);
     page_manager.consolidate_state()?;
     
-    for i in 0..page_manager.config_page.get_version_number() {
-        let _ptr = page_manager.config_page.get_recycled_pages_list_at(i);
+    for i in 0..page_manager.config_page().get_version_number() {
+        let _ptr = page_manager.config_page().get_recycled_pages_list_at(i)?;
         //debug!(log, "get_recycled_pages_list_at({}) -> {:?}", i, ptr);
         let vec = page_manager.get_free_list_page_at(i)?;
-        let fresh_pages = page_manager.config_page.get_last_used_page_at(i);
+        let fresh_pages = page_manager.config_page().get_last_used_page_at(i)?;
         debug!(log, "last_used_page {} get_free_pages({}) -> {:?}",fresh_pages,  i, vec)
     }
 
     //
 
-    debug!(log, "{:?} ", page_manager.config_page);
-    return Ok(());
+    debug!(log, "{:?} ", page_manager.config_page());
+    Ok(())
 }
@@ -1,83 +1,413 @@
+use crate::error::MemoryManagerError;
 use crate::logger;
-use memmap2::{MmapOptions, MmapRaw};
-use slog::{crit, info};
-use std::fs::OpenOptions;
-use std::io::{self, ErrorKind};
-use std::process;
+use slog::{debug, info, Logger};
+use std::collections::BTreeSet;
 use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
-use crate::pages::from_slice::FromSlice;
+use crate::pages::from_slice::{FromSlice, FromSliceRef};
+use crate::pages::generic_page::GenericPageRef;
+use crate::storage::file_store::FileStore;
+use crate::storage::memory_store::MemoryStore;
+use crate::storage::mmap_store::MmapStore;
+use crate::storage::segmented_store::SegmentedStore;
+use crate::storage::{check_page_range, PageStore};
+pub use crate::storage::{Advice, DEFAULT_PAGE_SIZE};
+
+mod borrow;
+mod options;
+use borrow::BorrowRegistry;
+pub use borrow::{PageGuard, PageRef};
+pub use options::MemoryManagerOptions;
 
-const PAGE_SIZE: u64 = 0x1000; // 4KB
 const _FRAGMENT_SIZE: usize = 0x10; // 4KB
 pub const RESERVED_CONFIG_PAGE_INDEX: u64 = 0;
+// The config page is double buffered: commits alternate between these two meta pages, so a
+// crash while writing one of them leaves the previous commit in the other one.
+pub const META_PAGE_INDEXES: [u64; 2] = [RESERVED_CONFIG_PAGE_INDEX, 1];
+// The page manager never hands out the pages before this one
+pub const FIRST_DATA_PAGE_INDEX: u64 = 2;
 
 #[derive(Debug)]
 pub struct MemoryManager {
-    mmap: MmapRaw,
+    store: Box<dyn PageStore>,
+    // Pages handed out as mutable since the last flush
+    dirty_pages: Mutex<BTreeSet<u64>>,
+    // Pages currently handed out through get_page and get_page_mut
+    borrows: Arc<BorrowRegistry>,
+    // Pages kept resident with lock_pages
+    locked_pages: Mutex<BTreeSet<u64>>,
+    // Whether the page manager should lock the config and free list pages
+    lock_metadata: bool,
+    logger: Logger,
 }
 
 impl MemoryManager {
-    pub fn new(filename: &str, num_pages: u64) -> Result<Self, std::io::Error> {
-        let log: &slog::Logger = logger::get_logger();
-        // Open the memory-mapped file
-        let file = match OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(filename)
-        {
-            Ok(file) => {
-                info!(log, "File {} opened", filename);
-                file
-            }
-            Err(e) => {
-                crit!(log, "Failed to open file: {} - {}", filename, e);
-                process::exit(1);
+    // Creates (or reuses) a memory mapped file with num_pages pages
+    pub fn new(filename: &str, num_pages: u64) -> Result<Self, MemoryManagerError> {
+        Self::new_with_page_size(filename, num_pages, DEFAULT_PAGE_SIZE)
+    }
+
+    // Same as `new` with pages of page_size bytes, a power of two between 4KB and 2MB.
+    // The page size is stored in the file, `open` reads it back.
+    pub fn new_with_page_size(
+        filename: &str,
+        num_pages: u64,
+        page_size: u64,
+    ) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(MmapStore::create(
+            filename,
+            num_pages,
+            page_size,
+            logger::get_logger(),
+        )?))
+    }
+
+    // Opens an existing database file, taking the number of pages from the file size.
+    // Unlike `new`, the file is never created, resized or truncated.
+    pub fn open(filename: &str) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(MmapStore::open(
+            filename,
+            logger::get_logger(),
+        )?))
+    }
+
+    // Opens an existing database file read-only, mapped with PROT_READ.
+    // Only immutable page views (get_page) are available, get_page_mut and resize fail.
+    pub fn open_read_only(filename: &str) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(MmapStore::open_read_only(
+            filename,
+            logger::get_logger(),
+        )?))
+    }
+
+    // Same as `new` but using pread/pwrite instead of mapping the file
+    pub fn new_file_io(filename: &str, num_pages: u64) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(FileStore::create(
+            filename,
+            num_pages,
+            DEFAULT_PAGE_SIZE,
+            logger::get_logger(),
+        )?))
+    }
+
+    // Same as `open` but using pread/pwrite instead of mapping the file
+    pub fn open_file_io(filename: &str) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(FileStore::open(
+            filename,
+            logger::get_logger(),
+        )?))
+    }
+
+    // Spreads the pages over segment files of pages_per_segment pages each (see
+    // SegmentedStore), instead of a single file. Segments are created as the memory grows.
+    pub fn new_segmented(
+        filename: &str,
+        num_pages: u64,
+        pages_per_segment: u64,
+    ) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(SegmentedStore::create(
+            filename,
+            num_pages,
+            DEFAULT_PAGE_SIZE,
+            pages_per_segment,
+            logger::get_logger(),
+        )?))
+    }
+
+    // Same as `open` for a database created with new_segmented, the segment size is read
+    // from the file header
+    pub fn open_segmented(filename: &str) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(SegmentedStore::open(
+            filename,
+            logger::get_logger(),
+        )?))
+    }
+
+    // Keeps the pages in an anonymous memory mapping, nothing is ever written to disk
+    pub fn in_memory(num_pages: u64) -> Result<Self, MemoryManagerError> {
+        Self::in_memory_with_page_size(num_pages, DEFAULT_PAGE_SIZE)
+    }
+
+    pub fn in_memory_with_page_size(
+        num_pages: u64,
+        page_size: u64,
+    ) -> Result<Self, MemoryManagerError> {
+        Ok(Self::with_store(MemoryStore::new(num_pages, page_size)?))
+    }
+
+    pub fn with_store<S: PageStore + 'static>(store: S) -> Self {
+        let logger = store.logger().clone();
+        MemoryManager {
+            store: Box::new(store),
+            dirty_pages: Mutex::new(BTreeSet::new()),
+            borrows: Arc::new(BorrowRegistry::default()),
+            locked_pages: Mutex::new(BTreeSet::new()),
+            lock_metadata: false,
+            logger,
+        }
+    }
+
+    // Logger of the memory manager and the page managers using it, by default the one of the
+    // store (tagged with the file name)
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    // Makes the store, the memory manager and the page managers created afterwards log through
+    // a child of parent, tagged with the file name like the one set with
+    // MemoryManagerOptions::logger. The constructors of MemoryManager use the global logger.
+    pub fn set_logger(&mut self, parent: Logger) {
+        self.store.set_logger(&parent);
+        self.logger = self.store.logger().clone();
+    }
+
+    // Number of pages currently backed by the store
+    pub fn num_pages(&self) -> u64 {
+        self.store.num_pages()
+    }
+
+    // Size of every page in bytes
+    pub fn page_size(&self) -> u64 {
+        self.store.page_size()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.store.is_read_only()
+    }
+
+    // Immutable view of a page, available on read-only managers too.
+    // Fails if the page is borrowed through get_page_mut.
+    pub fn get_page(&self, index: u64) -> Result<PageRef<GenericPageRef<'_>>, MemoryManagerError> {
+        self.get_page_ref(index)
+    }
+
+    // Immutable view of a page as T (ConfigPageRef, FreeListPageRef...).
+    // Any number of them can be alive at the same time, the page isn't marked dirty.
+    pub fn get_page_ref<'a, T: FromSliceRef<'a>>(
+        &'a self,
+        index: u64,
+    ) -> Result<PageRef<T>, MemoryManagerError> {
+        let ptr = self.store.get_page_ptr(index)?;
+        self.borrows.borrow(index, false)?;
+        let data = unsafe { slice::from_raw_parts(ptr, self.page_size() as usize) };
+        Ok(PageRef::new(
+            T::from_slice_ref(data),
+            index,
+            self.borrows.clone(),
+        ))
+    }
+
+    // Exclusive view of a page as T (ConfigPage, FreeListPage...).
+    // Fails if the page is out of bounds or already borrowed, so there is never more than
+    // one view of a page that can write to it.
+    pub fn get_page_mut<'a, T: FromSlice<'a>>(
+        &'a self,
+        index: u64,
+    ) -> Result<PageGuard<T>, MemoryManagerError> {
+        // The guard borrows self, the pages can't be resized away while it's alive
+        unsafe { self.get_page_mut_detached(index) }
+    }
+
+    // Same as get_page_mut but the view isn't tied to a borrow of the memory manager.
+    // Safety: the caller must not use the page after a resize or after the memory manager
+    // is dropped, see reload_page.
+    pub(crate) unsafe fn get_page_mut_detached<'b, T: FromSlice<'b>>(
+        &self,
+        index: u64,
+    ) -> Result<PageGuard<T>, MemoryManagerError> {
+        if self.is_read_only() {
+            return Err(MemoryManagerError::ReadOnly(format!(
+                "Cannot get page {} as mutable: read-only memory",
+                index
+            )));
+        }
+        let ptr = self.store.get_page_ptr(index)?;
+        self.borrows.borrow(index, true)?;
+        self.mark_dirty(index);
+        let data = slice::from_raw_parts_mut(ptr, self.page_size() as usize);
+        Ok(PageGuard::new(
+            T::from_slice(data),
+            index,
+            self.borrows.clone(),
+        ))
+    }
+
+    // Points a detached guard to the current location of its page, after a resize
+    // Safety: same as get_page_mut_detached
+    pub(crate) unsafe fn reload_page<'b, T: FromSlice<'b>>(
+        &self,
+        guard: &mut PageGuard<T>,
+    ) -> Result<(), MemoryManagerError> {
+        let ptr = self.store.get_page_ptr(guard.index())?;
+        self.mark_dirty(guard.index());
+        let data = slice::from_raw_parts_mut(ptr, self.page_size() as usize);
+        guard.replace(T::from_slice(data));
+        Ok(())
+    }
+
+    // Number of pages with a live guard
+    pub fn num_borrowed_pages(&self) -> u64 {
+        self.borrows.num_borrowed()
+    }
+
+    // Pages are tracked when they are handed out by get_page_mut. A page kept across a flush
+    // and written again afterwards has to be marked, otherwise the next flush skips it (and the
+    // file I/O store drops the change with its copy of the page).
+    pub fn mark_dirty(&self, index: u64) {
+        if !self.is_read_only() {
+            lock(&self.dirty_pages).insert(index);
+        }
+    }
+
+    // Number of pages waiting for the next flush
+    pub fn num_dirty_pages(&self) -> u64 {
+        lock(&self.dirty_pages).len() as u64
+    }
+
+    // Hints how num_pages pages starting at first_page are going to be accessed, e.g.
+    // Sequential before scanning a large region or WillNeed on a page we are about to read.
+    // The in-memory store ignores the hints.
+    pub fn advise(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        advice: Advice,
+    ) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages())?;
+        self.store.advise_range(first_page, num_pages, advice)
+    }
+
+    // Keeps num_pages pages starting at first_page resident in memory (mlock), so accessing
+    // them never page-faults. They stay locked across resizes until unlock_pages.
+    pub fn lock_pages(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages())?;
+        self.store.lock_range(first_page, num_pages, true)?;
+        lock(&self.locked_pages).extend(first_page..first_page + num_pages);
+        Ok(())
+    }
+
+    // Lets the pages be paged out again, pages that aren't locked are skipped
+    pub fn unlock_pages(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        let pages: BTreeSet<u64> = lock(&self.locked_pages)
+            .range(first_page..first_page.saturating_add(num_pages))
+            .copied()
+            .collect();
+        for (first_page, num_pages) in page_ranges(&pages) {
+            self.store.lock_range(first_page, num_pages, false)?;
+        }
+        lock(&self.locked_pages).retain(|page| !pages.contains(page));
+        Ok(())
+    }
+
+    pub fn num_locked_pages(&self) -> u64 {
+        lock(&self.locked_pages).len() as u64
+    }
+
+    // Set through MemoryManagerOptions::lock_metadata
+    pub fn locks_metadata(&self) -> bool {
+        self.lock_metadata
+    }
+
+    // Flushes the dirty pages and waits for them to reach the disk. It's a write barrier: the
+    // pages written before the call are durable before any page written after it.
+    // Returns the number of bytes flushed.
+    pub fn flush(&self) -> Result<u64, MemoryManagerError> {
+        self.flush_dirty_pages(false)
+    }
+
+    // Starts writing back the dirty pages without waiting for the disk.
+    // Returns the number of bytes scheduled.
+    pub fn flush_async(&self) -> Result<u64, MemoryManagerError> {
+        self.flush_dirty_pages(true)
+    }
+
+    // Flushes the whole store, dirty or not
+    pub fn flush_all(&self) -> Result<(), MemoryManagerError> {
+        self.store.flush()?;
+        lock(&self.dirty_pages).clear();
+        self.release_pages();
+        Ok(())
+    }
+
+    // Number of pages the store keeps a copy of in memory, only the file I/O store caches pages
+    pub fn num_cached_pages(&self) -> u64 {
+        self.store.num_cached_pages()
+    }
+
+    // Every page is clean after a flush, the store can drop the copies nobody is using.
+    // Pages with a live guard are kept, their pointers are still in use.
+    fn release_pages(&self) {
+        self.store
+            .release_pages(&|index| self.borrows.is_borrowed(index));
+    }
+
+    fn flush_dirty_pages(&self, asynchronous: bool) -> Result<u64, MemoryManagerError> {
+        // Another thread may mark pages dirty while we flush, only the ones flushed are cleared
+        let dirty_pages = lock(&self.dirty_pages).clone();
+        let ranges = page_ranges(&dirty_pages);
+        let mut flushed_pages = 0;
+        for &(first_page, num_pages) in &ranges {
+            if asynchronous {
+                self.store.flush_range_async(first_page, num_pages)?;
+            } else {
+                self.store.flush_range(first_page, num_pages)?;
             }
-        };
-        // Set the file size
-        let file_size: u64 = PAGE_SIZE as u64 * num_pages;
-        file.set_len(file_size).map_err(|e| {
-            let err_msg = format!("Failed to set file size: {} - {}", file_size, e);
-            crit!(log, "{}", &err_msg);
-            io::Error::new(ErrorKind::Other, err_msg)
-        })?;
-        info!(log, "File size: {:?} MB", file_size as f64 / 1_048_576.0);
-
-        // Open a memory map for the file
-        let mmap = MmapOptions::new().map_raw(&file).map_err(|e| {
-            let err_msg = format!("Failed to create memory map: {}", e);
-            crit!(log, "{}", &err_msg);
-            io::Error::new(ErrorKind::Other, err_msg)
-        })?;
-
-        info!(log, "Correctly mapped {} pages into memory", num_pages);
-
-        Ok(MemoryManager { mmap: mmap })
-    }
-
-    pub fn get_page_mut<'a, T: FromSlice<'a>>(&self, index: u64) -> Result<T, std::io::Error> {
-        let offset = (index * PAGE_SIZE).try_into().map_err(|_| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Index {} is out of bounds", index),
-            )
-        })?;
-        unsafe {
-            let data = slice::from_raw_parts_mut(
-                self.mmap.as_mut_ptr().offset(offset),
-                PAGE_SIZE as usize,
-            );
-            Ok(T::from_slice(data))
+            flushed_pages += num_pages;
         }
+        lock(&self.dirty_pages).retain(|page| !dirty_pages.contains(page));
+        self.release_pages();
+
+        debug!(
+            self.logger,
+            "Flushed {} pages in {} ranges",
+            flushed_pages,
+            ranges.len()
+        );
+        Ok(flushed_pages * self.page_size())
     }
 
-    pub fn flush(&self) -> Result<(), std::io::Error> {
-        self.mmap.flush().map_err(|e| {
-            let err_msg = format!("Flush has failed: {}", e);
-            io::Error::new(ErrorKind::Other, err_msg)
-        })?;
+    // Grows or shrinks the store to num_pages.
+    // The pages may move, so every page obtained through get_page_mut before the call
+    // is invalid afterwards and has to be requested again.
+    pub fn resize(&mut self, num_pages: u64) -> Result<(), MemoryManagerError> {
+        let previous_num_pages = self.store.num_pages();
+        self.store.resize(num_pages)?;
+        lock(&self.dirty_pages).retain(|&page| page < num_pages);
+        // The locks belong to the previous mapping
+        let locked_pages = {
+            let mut locked_pages = lock(&self.locked_pages);
+            locked_pages.retain(|&page| page < num_pages);
+            page_ranges(&locked_pages)
+        };
+        for (first_page, num_pages) in locked_pages {
+            self.store.lock_range(first_page, num_pages, true)?;
+        }
+        info!(
+            self.logger,
+            "Resized from {} to {} pages ({:?} MB)",
+            previous_num_pages,
+            num_pages,
+            (self.page_size() * num_pages) as f64 / 1_048_576.0
+        );
         Ok(())
     }
 }
+
+// The sets behind the mutexes are never left half updated, a thread that panicked while
+// holding one of them doesn't make the memory manager unusable
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Pages merged into (first_page, num_pages) ranges of consecutive pages
+fn page_ranges(pages: &BTreeSet<u64>) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = vec![];
+    for &page in pages.iter() {
+        match ranges.last_mut() {
+            Some((first_page, num_pages)) if *first_page + *num_pages == page => *num_pages += 1,
+            _ => ranges.push((page, 1)),
+        }
+    }
+    ranges
+}
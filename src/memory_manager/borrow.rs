@@ -0,0 +1,159 @@
+use super::lock;
+use crate::error::MemoryManagerError;
+use crate::pages::from_slice::Reborrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+// Runtime borrow tracking for the pages handed out by MemoryManager, the same rules as a
+// RefCell per page: any number of shared guards or a single exclusive one.
+// The registry is shared with the guards so a guard can outlive the borrow of the
+// MemoryManager it came from (see PageManager::config_page). It's behind a mutex so the
+// memory manager can be shared between threads.
+#[derive(Debug, Default)]
+pub(crate) struct BorrowRegistry {
+    // > 0: number of shared guards, -1: exclusive guard
+    pages: Mutex<HashMap<u64, isize>>,
+}
+
+impl BorrowRegistry {
+    pub(crate) fn borrow(&self, index: u64, exclusive: bool) -> Result<(), MemoryManagerError> {
+        let mut pages = lock(&self.pages);
+        let state = pages.entry(index).or_insert(0);
+        match (*state, exclusive) {
+            (0, true) => *state = -1,
+            (count, false) if count >= 0 => *state += 1,
+            (count, _) => {
+                return Err(MemoryManagerError::Borrowed {
+                    page: index,
+                    exclusive: count == -1,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn release(&self, index: u64, exclusive: bool) {
+        let mut pages = lock(&self.pages);
+        if let Some(state) = pages.get_mut(&index) {
+            if exclusive {
+                *state = 0;
+            } else {
+                *state -= 1;
+            }
+            if *state == 0 {
+                pages.remove(&index);
+            }
+        }
+    }
+
+    pub(crate) fn is_borrowed(&self, index: u64) -> bool {
+        lock(&self.pages).contains_key(&index)
+    }
+
+    pub(crate) fn num_borrowed(&self) -> u64 {
+        lock(&self.pages).len() as u64
+    }
+}
+
+// Exclusive access to a page through the page type T (ConfigPage, FreeListPage...).
+// Dereferences to T for reading, page_mut lends a view to write. Releases the page when
+// dropped.
+pub struct PageGuard<T> {
+    page: T,
+    index: u64,
+    registry: Arc<BorrowRegistry>,
+}
+
+impl<T> PageGuard<T> {
+    // The page has to be registered as exclusively borrowed already
+    pub(crate) fn new(page: T, index: u64, registry: Arc<BorrowRegistry>) -> Self {
+        PageGuard {
+            page,
+            index,
+            registry,
+        }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    // Writable view of the page, it borrows the guard so it can't outlive it
+    pub fn page_mut(&mut self) -> T::Target<'_>
+    where
+        T: Reborrow,
+    {
+        self.page.reborrow()
+    }
+
+    // Swaps the view for a new one of the same page, keeping the borrow.
+    // Used after a resize, when the page may have moved.
+    pub(crate) fn replace(&mut self, page: T) {
+        self.page = page;
+    }
+}
+
+impl<T> Deref for PageGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.page
+    }
+}
+
+impl<T> Drop for PageGuard<T> {
+    fn drop(&mut self) {
+        self.registry.release(self.index, true);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PageGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.page.fmt(f)
+    }
+}
+
+// Shared, read-only access to a page through the page type T (&[u8], ConfigPageRef...).
+// Dereferences to T and releases the page when dropped.
+pub struct PageRef<T> {
+    page: T,
+    index: u64,
+    registry: Arc<BorrowRegistry>,
+}
+
+impl<T> PageRef<T> {
+    // The page has to be registered as borrowed (shared) already
+    pub(crate) fn new(page: T, index: u64, registry: Arc<BorrowRegistry>) -> Self {
+        PageRef {
+            page,
+            index,
+            registry,
+        }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+}
+
+impl<T> Deref for PageRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.page
+    }
+}
+
+impl<T> Drop for PageRef<T> {
+    fn drop(&mut self) {
+        self.registry.release(self.index, false);
+    }
+}
+
+impl<T> fmt::Debug for PageRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PageRef {{ index: {} }}", self.index)
+    }
+}
@@ -0,0 +1,269 @@
+use super::{MemoryManager, DEFAULT_PAGE_SIZE, META_PAGE_INDEXES, RESERVED_CONFIG_PAGE_INDEX};
+use crate::error::MemoryManagerError;
+use crate::logger;
+use crate::storage::file_store::FileStore;
+use crate::storage::memory_store::MemoryStore;
+use crate::storage::mmap_store::MmapStore;
+use crate::storage::segmented_store::SegmentedStore;
+use crate::storage::{create_file, open_file, MapFlags, PageStore};
+use slog::{crit, info, Logger};
+
+// Knobs for opening a MemoryManager, in the style of std::fs::OpenOptions:
+//
+//     let memory = MemoryManagerOptions::new()
+//         .populate(true)
+//         .lock_metadata(true)
+//         .open("db.bin", 1024)?;
+//
+// The defaults behave like MemoryManager::new. read_only, file_io, pages_per_segment and
+// in_memory pick the other stores, so every store can be given a logger.
+#[derive(Debug, Clone)]
+pub struct MemoryManagerOptions {
+    page_size: u64,
+    create: bool,
+    truncate: bool,
+    populate: bool,
+    huge_pages: bool,
+    lock_metadata: bool,
+    read_only: bool,
+    file_io: bool,
+    pages_per_segment: u64,
+    logger: Option<Logger>,
+}
+
+impl Default for MemoryManagerOptions {
+    fn default() -> Self {
+        MemoryManagerOptions {
+            page_size: DEFAULT_PAGE_SIZE,
+            create: true,
+            truncate: false,
+            populate: false,
+            huge_pages: false,
+            lock_metadata: false,
+            read_only: false,
+            file_io: false,
+            pages_per_segment: 0,
+            logger: None,
+        }
+    }
+}
+
+impl MemoryManagerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Size of the pages of a new file, see MemoryManager::new_with_page_size
+    pub fn page_size(&mut self, page_size: u64) -> &mut Self {
+        self.page_size = page_size;
+        self
+    }
+
+    // Create the file if it doesn't exist (default), resizing it to the requested number of
+    // pages. Otherwise opening a missing file fails and an existing file keeps its length.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    // Discard the contents of an existing file, every page starts zeroed
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    // Pre-fault the whole mapping when it's created (MAP_POPULATE) so the first access to
+    // a page doesn't page-fault. The mapping is populated again every time it grows.
+    // Only used by the default store, a single memory mapped file.
+    pub fn populate(&mut self, populate: bool) -> &mut Self {
+        self.populate = populate;
+        self
+    }
+
+    // Advise the kernel to back the mapping with transparent huge pages.
+    // Only used by the default store, a single memory mapped file.
+    pub fn huge_pages(&mut self, huge_pages: bool) -> &mut Self {
+        self.huge_pages = huge_pages;
+        self
+    }
+
+    // mlock the meta pages, and let the page manager mlock the free list page it's using,
+    // so committing never waits for them to be paged in
+    pub fn lock_metadata(&mut self, lock_metadata: bool) -> &mut Self {
+        self.lock_metadata = lock_metadata;
+        self
+    }
+
+    // Open an existing file read-only, see MemoryManager::open_read_only. The file is never
+    // created, num_pages is ignored.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    // Read and write the pages with pread/pwrite instead of mapping the file, see
+    // MemoryManager::new_file_io
+    pub fn file_io(&mut self, file_io: bool) -> &mut Self {
+        self.file_io = file_io;
+        self
+    }
+
+    // Spread the pages over segment files of pages_per_segment pages, see
+    // MemoryManager::new_segmented. 0 (default) keeps them in a single file. With create(false)
+    // it must be the segment size recorded in the file.
+    pub fn pages_per_segment(&mut self, pages_per_segment: u64) -> &mut Self {
+        self.pages_per_segment = pages_per_segment;
+        self
+    }
+
+    // Parent of the logger the memory manager and its page managers log through, a child
+    // tagged with the file name is created from it. Defaults to the global terminal logger.
+    pub fn logger(&mut self, logger: Logger) -> &mut Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    // Opens (or creates) the file and resizes it to num_pages pages.
+    // With create(false) and truncate(false) num_pages is ignored, the file keeps its length.
+    pub fn open(
+        &self,
+        filename: &str,
+        num_pages: u64,
+    ) -> Result<MemoryManager, MemoryManagerError> {
+        let parent = self.parent_logger();
+        let log = logger::file_logger(parent, filename);
+        info!(log, "Opening {} with {:?}", filename, self);
+        self.check_backend(&log)?;
+
+        let memory = if self.read_only {
+            let memory = MemoryManager::with_store(MmapStore::open_read_only(filename, parent)?);
+            self.check_page_size(memory.page_size(), &log)?;
+            memory
+        } else if self.pages_per_segment != 0 {
+            if self.create {
+                MemoryManager::with_store(SegmentedStore::create(
+                    filename,
+                    num_pages,
+                    self.page_size,
+                    self.pages_per_segment,
+                    parent,
+                )?)
+            } else {
+                let store = SegmentedStore::open(filename, parent)?;
+                self.check_pages_per_segment(store.pages_per_segment(), &log)?;
+                self.check_page_size(store.page_size(), &log)?;
+                MemoryManager::with_store(store)
+            }
+        } else {
+            let (file, num_pages) = if self.create || self.truncate {
+                let file = create_file(
+                    filename,
+                    num_pages,
+                    self.page_size,
+                    0,
+                    self.create,
+                    self.truncate,
+                    &log,
+                )?;
+                (file, num_pages)
+            } else {
+                let (file, file_pages, file_page_size) = open_file(filename, true, &log)?;
+                self.check_page_size(file_page_size, &log)?;
+                (file, file_pages)
+            };
+            if self.file_io {
+                MemoryManager::with_store(FileStore::from_file(
+                    filename,
+                    file,
+                    num_pages,
+                    self.page_size,
+                    log,
+                ))
+            } else {
+                let flags = MapFlags {
+                    populate: self.populate,
+                    huge_pages: self.huge_pages,
+                };
+                MemoryManager::with_store(MmapStore::map_file(
+                    filename,
+                    file,
+                    num_pages,
+                    self.page_size,
+                    flags,
+                    log,
+                )?)
+            }
+        };
+        self.setup(memory)
+    }
+
+    // Keeps num_pages pages in an anonymous memory mapping, see MemoryManager::in_memory.
+    // Only page_size, lock_metadata and logger apply, there's no file.
+    pub fn in_memory(&self, num_pages: u64) -> Result<MemoryManager, MemoryManagerError> {
+        let mut store = MemoryStore::new(num_pages, self.page_size)?;
+        store.set_logger(self.parent_logger());
+        self.setup(MemoryManager::with_store(store))
+    }
+
+    fn parent_logger(&self) -> &Logger {
+        self.logger.as_ref().unwrap_or(logger::get_logger())
+    }
+
+    // read_only and pages_per_segment pick their own store, they can't be mixed with the
+    // options of the others
+    fn check_backend(&self, log: &Logger) -> Result<(), MemoryManagerError> {
+        let conflict = if self.read_only && (self.truncate || self.file_io) {
+            Some("read_only can't be combined with truncate or file_io")
+        } else if self.read_only && self.pages_per_segment != 0 {
+            Some("read_only can't be combined with pages_per_segment")
+        } else if self.pages_per_segment != 0 && (self.truncate || self.file_io) {
+            Some("pages_per_segment can't be combined with truncate or file_io")
+        } else {
+            None
+        };
+        if let Some(err_msg) = conflict {
+            crit!(log, "{}", err_msg);
+            return Err(MemoryManagerError::InvalidInput(err_msg.to_string()));
+        }
+        Ok(())
+    }
+
+    // An existing file must have been created with the requested page size
+    fn check_page_size(&self, file_page_size: u64, log: &Logger) -> Result<(), MemoryManagerError> {
+        if file_page_size != self.page_size {
+            let err_msg = format!(
+                "Page size mismatch: file page size: {}, requested page size: {}",
+                file_page_size, self.page_size
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::InvalidInput(err_msg));
+        }
+        Ok(())
+    }
+
+    // An existing segmented database must have been created with the requested segment size
+    fn check_pages_per_segment(
+        &self,
+        file_pages_per_segment: u64,
+        log: &Logger,
+    ) -> Result<(), MemoryManagerError> {
+        if file_pages_per_segment != self.pages_per_segment {
+            let err_msg = format!(
+                "Segment size mismatch: file pages per segment: {}, requested pages per segment: {}",
+                file_pages_per_segment, self.pages_per_segment
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::InvalidInput(err_msg));
+        }
+        Ok(())
+    }
+
+    fn setup(&self, mut memory: MemoryManager) -> Result<MemoryManager, MemoryManagerError> {
+        if self.lock_metadata {
+            memory.lock_metadata = true;
+            let meta_pages = (META_PAGE_INDEXES.len() as u64).min(memory.num_pages());
+            memory.lock_pages(RESERVED_CONFIG_PAGE_INDEX, meta_pages)?;
+        }
+        Ok(memory)
+    }
+}
@@ -0,0 +1,40 @@
+use crate::error::MemoryManagerError;
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+
+// Metadata pages (config pages and free list pages) end with a CRC32C of the rest of the page.
+// It's written when the state is consolidated and verified whenever the page is loaded.
+pub const CHECKSUM_BYTES: usize = 4;
+
+// Where the checksum starts in a page of page_len bytes
+pub fn checksum_start(page_len: usize) -> usize {
+    page_len - CHECKSUM_BYTES
+}
+
+pub fn compute_checksum(data: &[u8]) -> u32 {
+    crc32c::crc32c(&data[..checksum_start(data.len())])
+}
+
+pub fn read_checksum(data: &[u8]) -> u32 {
+    LittleEndian::read_u32(&data[checksum_start(data.len())..])
+}
+
+pub fn write_checksum(data: &mut [u8]) {
+    let checksum = compute_checksum(data);
+    let start = checksum_start(data.len());
+    LittleEndian::write_u32(&mut data[start..], checksum);
+}
+
+// page is only used to report the error
+pub fn verify_checksum(data: &[u8], page: u64) -> Result<(), MemoryManagerError> {
+    let stored = read_checksum(data);
+    let computed = compute_checksum(data);
+    if stored != computed {
+        return Err(MemoryManagerError::ChecksumMismatch {
+            page,
+            stored,
+            computed,
+        });
+    }
+    Ok(())
+}
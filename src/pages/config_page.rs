@@ -1,26 +1,98 @@
-use byteorder::ByteOrder;
-use byteorder::LittleEndian;
-use std::fmt;
+use crate::error::MemoryManagerError;
+use crate::pages::from_slice::{FromSlice, FromSliceRef};
+use crate::u48::{U24, U32, U40, U48, U64};
+
+// The file header (superblock) is stored once, at the beginning of the config page.
+// pages_per_segment is the segment size of a database spread over several files (see
+// SegmentedStore), 0 for a single file. write_superblock doesn't touch it, the storage layer
+// sets it when it creates the file.
+crate::page_layout! {
+    pub struct Superblock, SuperblockRef {
+        start: 0,
+        fields {
+            page_size: U32,
+            magic: U64,
+            format_version: U32,
+            feature_flags: U64,
+            pages_per_segment: U64,
+        }
+    }
+}
 
-// Defining constants to avoid magic numbers
-const TOTAL_ALLOCATED_PAGES_BYTES: usize = 6;
-const TOTAL_ALLOCATED_PAGES_START: usize = 0; // 6 bytes
+// Reserved for the file header, the config history starts right after it
+pub const FILE_HEADER_BYTES: usize = SuperblockRef::RECORD_BYTES;
 
-const VERSION_NUMBER_BYTES: usize = 5;
-const VERSION_NUMBER_START: usize = TOTAL_ALLOCATED_PAGES_START + TOTAL_ALLOCATED_PAGES_BYTES; // 5 bytes
+// Identifies our files, anything else is refused
+pub const MAGIC: [u8; U64::BYTES] = *b"MEMMGRDB";
 
-const LAST_USED_PAGE_BYTES: usize = 6;
-const LAST_USED_PAGE_START: usize = VERSION_NUMBER_START + VERSION_NUMBER_BYTES; // 6 bytes
+// Bumped whenever the on-disk layout changes. There's no upgrade path between versions, files
+// with any other format are refused. Version 2 added the metadata page checksums, version 3
+// stores the free list entries in 6 bytes and version 4 the number of free pages of every
+// free list chain.
+pub const FORMAT_VERSION: u64 = 4;
 
-const RECYCLED_PAGES_LIST_BYTES: usize = 6;
-const RECYCLED_PAGES_LIST_START: usize = LAST_USED_PAGE_START + LAST_USED_PAGE_BYTES; // 6 bytes
+// Feature flags this build understands. A file using any other flag is refused.
+pub const SUPPORTED_FEATURE_FLAGS: u64 = 0;
 
-const PREVIOUS_CONFIG_PAGE_BYTES: usize = 6;
-const PREVIOUS_CONFIG_PAGE_START: usize = RECYCLED_PAGES_LIST_START + RECYCLED_PAGES_LIST_BYTES; // 6 bytes
+// The config header is stored in history slots after the file header, see ConfigPage.
+// Slot 0 holds the current header and slot N the header of a previous version.
+const HISTORY_START: usize = FILE_HEADER_BYTES;
+const SLOT_BYTES: usize = ConfigPageRef::RECORD_BYTES;
+
+// Reads the page size from the beginning of a config page (or of the file)
+pub fn page_size_from_header(header: &[u8]) -> u64 {
+    SuperblockRef::from_slice_ref(header).get_page_size()
+}
+
+// Writes a fresh file header for pages of page_size bytes
+pub fn write_superblock(header: &mut [u8], page_size: u64) -> Result<(), MemoryManagerError> {
+    let mut superblock = Superblock::from_slice(header);
+    superblock.set_page_size(page_size)?;
+    superblock.set_magic(u64::from_le_bytes(MAGIC))?;
+    superblock.set_format_version(FORMAT_VERSION)?;
+    superblock.set_feature_flags(0)
+}
+
+// Reads the segment size from the beginning of the file, 0 if it isn't segmented
+pub fn pages_per_segment_from_header(header: &[u8]) -> u64 {
+    SuperblockRef::from_slice_ref(header).get_pages_per_segment()
+}
 
-const OFFSET_BYTES: usize = 3;
-const OFFSET_START: usize = PREVIOUS_CONFIG_PAGE_START + PREVIOUS_CONFIG_PAGE_BYTES; // 3 bytes
-const OFFSET_END: usize = OFFSET_START + OFFSET_BYTES;
+pub fn set_pages_per_segment(
+    header: &mut [u8],
+    pages_per_segment: u64,
+) -> Result<(), MemoryManagerError> {
+    Superblock::from_slice(header).set_pages_per_segment(pages_per_segment)
+}
+
+// Checks that the header belongs to a database this build can read. The page size is
+// validated by the storage layer.
+pub fn check_superblock(header: &[u8]) -> Result<(), MemoryManagerError> {
+    let superblock = SuperblockRef::from_slice_ref(header);
+    let magic = superblock.get_magic().to_le_bytes();
+    if magic != MAGIC {
+        return Err(MemoryManagerError::BadMagic { found: magic });
+    }
+    let format_version = superblock.get_format_version();
+    if format_version != FORMAT_VERSION {
+        return Err(MemoryManagerError::UnsupportedFormatVersion {
+            version: format_version,
+            supported: FORMAT_VERSION,
+        });
+    }
+    let feature_flags = superblock.get_feature_flags();
+    if feature_flags & !SUPPORTED_FEATURE_FLAGS != 0 {
+        return Err(MemoryManagerError::UnsupportedFeatures {
+            flags: feature_flags & !SUPPORTED_FEATURE_FLAGS,
+        });
+    }
+    Ok(())
+}
+
+// A header that was never written: an in-memory store or a database that isn't initialized
+pub fn is_blank_header(header: &[u8]) -> bool {
+    header[..FILE_HEADER_BYTES].iter().all(|&byte| byte == 0)
+}
 
 #[derive(Debug, Default, PartialEq)]
 #[repr(C)]
@@ -45,116 +117,99 @@ impl MemoryLayout {
         result.extend_from_slice(&self.offset.to_le_bytes());
         result
     }
-    pub fn from_bytes_at(config_page: &ConfigPage, version: u64) -> Option<Self> {
-        Some(Self {
-            total_allocated_pages: config_page.get_total_allocated_pages_at(version),
-            version_number: config_page.get_version_number_at(version),
-            last_used_page: config_page.get_last_used_page_at(version),
-            recycled_pages_list: config_page.get_recycled_pages_list_at(version),
-            previous_config_page: config_page.get_previous_config_page_at(version),
-            offset: config_page.get_offset_at(version),
+    // Accepts any view of a config page: ConfigPage, ConfigPageRef or the guards holding them
+    pub fn from_bytes_at<'b>(
+        config_page: impl Into<ConfigPageRef<'b>>,
+        version: u64,
+    ) -> Result<Self, MemoryManagerError> {
+        let config_page = config_page.into();
+        Ok(Self {
+            total_allocated_pages: config_page.get_total_allocated_pages_at(version)?,
+            version_number: config_page.get_version_number_at(version)?,
+            last_used_page: config_page.get_last_used_page_at(version)?,
+            recycled_pages_list: config_page.get_recycled_pages_list_at(version)?,
+            previous_config_page: config_page.get_previous_config_page_at(version)?,
+            offset: config_page.get_offset_at(version)?,
         })
     }
 }
 
-macro_rules! impl_set_get {
-    ($name:ident, $start_const:ident, $num_bytes:expr) => {
-        paste::paste! {  // Usamos el crate 'paste' para concatenar identificadores
+// The config header, stored in history slots after the file header. Page ids are U48, the
+// version number U40 and the offset U24.
+crate::page_layout! {
+    pub struct ConfigPage, ConfigPageRef {
+        start: HISTORY_START,
+        history: check_version,
+        fields {
+            total_allocated_pages: U48,
+            version_number: U40,
+            last_used_page: U48,
+            recycled_pages_list: U48,
+            previous_config_page: U48,
+            offset: U24,
+        }
+    }
+}
 
-            pub fn [<get_ $name>](&self) -> u64 {
-                let mut value = LittleEndian::read_u64(&self.data[$start_const..$start_const + 8]);
-                let mask = !0u64 >> (8 * (8 - $num_bytes));
-                value &= mask;
-                value
-            }
-            #[allow(dead_code)]
-            pub fn [<get_ $name _at>](&self, version: u64) -> u64 {
-                if version > self.get_version_number() {
-                    panic!("Version number is greater than the current version number");
-                }
-                let selector = version as usize * OFFSET_END;
-                let mut value;
-                // we have an issue with the latest element of a [u8;4096] since we only have 4096 bytes, so we need to copy and extend the last element
-                if selector + $start_const + 8 > self.data.len() {
-                    let mut extended = [0u8; 8]; // Inicializar un array de 8 bytes con 0s
-                    extended[..3].copy_from_slice(&self.data[selector + $start_const.. selector + $start_const + $num_bytes]); // Copiar los 3 bytes del array original
-                    value = LittleEndian::read_u64(&extended[..8]);
-                }else{
-                    value = LittleEndian::read_u64(&self.data[selector + $start_const.. selector +$start_const + 8]);
-
-                }
-
-                let mask = !0u64 >> (8 * (8 - $num_bytes));
-                value &= mask;
-                value
-            }
+// The file header getters of ConfigPage and ConfigPageRef
+macro_rules! impl_config_page_getters {
+    () => {
+        pub fn get_page_size(&self) -> u64 {
+            page_size_from_header(self.data)
+        }
+
+        pub fn get_format_version(&self) -> u64 {
+            SuperblockRef::from_slice_ref(self.data).get_format_version()
+        }
 
-            pub fn [<set_ $name>](&mut self, value: u64) {
-                let mut buf = [0u8; 8];
-                LittleEndian::write_u64(&mut buf, value);
-                self.data[$start_const..$start_const + $num_bytes]
-                    .copy_from_slice(&buf[0..$num_bytes]);
+        pub fn get_feature_flags(&self) -> u64 {
+            SuperblockRef::from_slice_ref(self.data).get_feature_flags()
+        }
+
+        // Slot 0 holds the current header, the history can't go past the current version or
+        // past the end of the page
+        fn check_version(&self, version: u64) -> Result<(), MemoryManagerError> {
+            let max_version = self.get_version_number().min(self.get_history_slots() - 1);
+            if version > max_version {
+                return Err(MemoryManagerError::VersionOutOfRange {
+                    version,
+                    max_version,
+                });
             }
+            Ok(())
         }
     };
 }
 
-// TODO: Error handling
+impl<'a> ConfigPage<'a> {
+    impl_config_page_getters!();
 
-#[derive(PartialEq)]
-pub struct ConfigPage<'a> {
-    pub data: &'a mut [u8],
-}
+    pub fn set_page_size(&mut self, value: u64) -> Result<(), MemoryManagerError> {
+        Superblock::from_slice(self.data).set_page_size(value)
+    }
 
-impl<'a> ConfigPage<'a> {
-    impl_set_get!(
-        total_allocated_pages,
-        TOTAL_ALLOCATED_PAGES_START,
-        TOTAL_ALLOCATED_PAGES_BYTES
-    );
-    impl_set_get!(version_number, VERSION_NUMBER_START, VERSION_NUMBER_BYTES);
-    impl_set_get!(last_used_page, LAST_USED_PAGE_START, LAST_USED_PAGE_BYTES);
-    impl_set_get!(
-        recycled_pages_list,
-        RECYCLED_PAGES_LIST_START,
-        RECYCLED_PAGES_LIST_BYTES
-    );
-    impl_set_get!(
-        previous_config_page,
-        PREVIOUS_CONFIG_PAGE_START,
-        PREVIOUS_CONFIG_PAGE_BYTES
-    );
-    impl_set_get!(offset, OFFSET_START, OFFSET_BYTES);
+    // Writes a fresh file header, see write_superblock
+    pub fn init_superblock(&mut self, page_size: u64) -> Result<(), MemoryManagerError> {
+        write_superblock(self.data, page_size)
+    }
 
     pub fn copy_header_to_offset(&mut self) {
-        let mut bytes = [0u8; OFFSET_END];
-        bytes.copy_from_slice(&self.data[0..OFFSET_END]);
-        let sel = (self.get_offset() * OFFSET_END as u64) as usize;
-        self.data[sel..sel + OFFSET_END].copy_from_slice(&bytes);
+        let sel = HISTORY_START + self.get_offset() as usize * SLOT_BYTES;
+        self.data
+            .copy_within(HISTORY_START..HISTORY_START + SLOT_BYTES, sel);
     }
     // Method to set the contents of this ConfigPage with the contents of another ConfigPage.
-    pub fn copy_config_page(&mut self, config: &ConfigPage) {
-        self.data[0..4096].copy_from_slice(&config.data[0..4096]); // Copying the data from config into self.
+    pub fn copy_config_page<'b>(&mut self, config: impl Into<ConfigPageRef<'b>>) {
+        self.data.copy_from_slice(config.into().data); // Copying the data from config into self.
     }
 
+    // Copies the file header and the current config header
     pub fn copy_config_page_header(&mut self, config: &ConfigPage) {
-        self.data[0..32].copy_from_slice(&config.data[0..32]); // Copying the data from config into self.
+        self.data[0..HISTORY_START + SLOT_BYTES]
+            .copy_from_slice(&config.data[0..HISTORY_START + SLOT_BYTES]); // Copying the data from config into self.
     }
 }
 
-// Implementing the Debug trait for ConfigPage to enable custom debug formatting.
-impl<'a> fmt::Debug for ConfigPage<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Custom formatting to display the result of get_total_allocated_pages when formatting ConfigPage for debugging.
-        write!(
-            f,
-            "ConfigPage {{ total_allocated_pages: {:?}, version_number: {:?}, last_used_page: {:?}, recycled_pages_list: {:?}, previous_config_page: {:?}, offset: {:?} }}",
-            &self.get_total_allocated_pages(),
-            &self.get_version_number(),
-            &self.get_last_used_page(),
-            &self.get_recycled_pages_list(),
-            &self.get_previous_config_page(),
-            &self.get_offset()
-        )
-    }
+impl<'a> ConfigPageRef<'a> {
+    impl_config_page_getters!();
 }
@@ -1,144 +1,191 @@
-use crate::memory_manager::MemoryManager;
-use byteorder::ByteOrder;
-use byteorder::LittleEndian;
-use byteorder::ReadBytesExt;
+use crate::error::MemoryManagerError;
+use crate::memory_manager::{self, MemoryManager, PageGuard};
+use crate::pages::checksum::CHECKSUM_BYTES;
+use crate::u48::{U32, U48};
 use rayon::iter::IndexedParallelIterator;
 use rayon::iter::IntoParallelRefMutIterator;
 use rayon::iter::ParallelIterator;
-use std::io::Cursor;
-use std::{fmt, vec};
+use std::vec;
+
+// The header links the chain of free list pages, the page ids follow it. chain_free_pages is
+// the number of page ids stored in this page and the ones after it in the chain, so a chain
+// that lost some of its pages can be told apart (see PageManager::check). reserved is always 0.
+crate::page_layout! {
+    pub struct FreeListPage, FreeListPageRef {
+        start: 0,
+        debug: [first_free_pages],
+        fields {
+            free_list_page_next: U48,
+            chain_free_pages: U48,
+            reserved: U32,
+        }
+    }
+}
+
+const HEADER_BYTES: usize = FreeListPageRef::RECORD_BYTES;
+const DATA_START: usize = FreeListPageRef::START + HEADER_BYTES;
+// Every entry is a page id, a U48
+const ENTRY_BYTES: usize = U48::BYTES;
 
-#[derive(PartialEq)]
-pub struct FreeListPage<'a> {
-    pub data: &'a mut [u8],
+// Number of page ids a free list page of page_size bytes can hold, the checksum takes the
+// last bytes of the page
+pub fn free_list_capacity(page_size: u64) -> usize {
+    (page_size as usize - DATA_START - CHECKSUM_BYTES) / ENTRY_BYTES
 }
 
-// Defining constants to avoid magic numbers
-const FREE_LIST_PAGE_NEXT_START: usize = 0;
-#[allow(dead_code)]
-const FREE_LIST_PAGE_NEXT_END: usize = 6;
-const DATA_START: usize = 16;
-const DATA_END: usize = 4096;
+fn data_end(page_len: usize) -> usize {
+    DATA_START + free_list_capacity(page_len as u64) * ENTRY_BYTES
+}
 
 impl<'a> FreeListPage<'a> {
-    // Initialize free list pages
+    // Chains every page of the memory into free list pages, the first pages of the chain hold
+    // the rest. It starts at FIRST_DATA_PAGE_INDEX, the meta pages before it are left alone.
     #[allow(dead_code)]
     pub fn init_free_list_pages(
         &mut self,
         memory: &MemoryManager,
         num_pages: u64,
-    ) -> Result<(), std::io::Error> {
+    ) -> Result<(), MemoryManagerError> {
         // Generate a vector of free page indices
-        let mut free_page_indices: Vec<u64> = (1..num_pages).collect();
+        let mut free_page_indices: Vec<u64> =
+            (memory_manager::FIRST_DATA_PAGE_INDEX..num_pages).collect();
+
+        let capacity = free_list_capacity(memory.page_size());
 
         // Calculate the number of chunks needed
-        let num_chunks = (free_page_indices.len() + 679) / 680;
+        let num_chunks = free_page_indices.len().div_ceil(capacity + 1);
         let chunked_free_page_indices: Vec<_> = free_page_indices.drain(..num_chunks).collect();
 
-        let mut free_list_pages: Vec<FreeListPage> = vec![];
+        let mut free_list_pages: Vec<PageGuard<FreeListPage>> = vec![];
 
         // Retrieve the free list pages from memory
         for (i, free_list_page) in chunked_free_page_indices.iter().enumerate() {
             free_list_pages.push(memory.get_page_mut::<FreeListPage>(*free_list_page)?);
             if i == num_chunks - 1 {
                 // If we are at the last free list page, we need to set the free pages list to 0
-                free_list_pages[i].set_free_list_page_next(0u64);
+                free_list_pages[i]
+                    .page_mut()
+                    .set_free_list_page_next(0u64)?;
             } else {
-                free_list_pages[i].set_free_list_page_next(chunked_free_page_indices[i + 1]);
+                free_list_pages[i]
+                    .page_mut()
+                    .set_free_list_page_next(chunked_free_page_indices[i + 1])?;
             }
         }
 
-        let bytes = &mut [0u8; 8]; // Un array de 8 bytes para almacenar u64
-
         // Process chunks of free pages
-        for (i, chunk) in free_page_indices.chunks(680).enumerate() {
-            let mut byte_vector: Vec<u8> = Vec::with_capacity(4080);
-            for u48 in chunk {
-                LittleEndian::write_u64(bytes, *u48); // Aquí escribes el u64 en el array de bytes
-                byte_vector.extend_from_slice(bytes);
-            }
-            // Pad with zeros to meet the required length
-            byte_vector.resize(4080, 0);
-            // Set free pages list slice
-            free_list_pages[i].set_free_list_page_data_slice(&byte_vector);
+        let mut chain_free_pages = free_page_indices.len() as u64;
+        for (i, chunk) in free_page_indices.chunks(capacity).enumerate() {
+            let mut free_list_page = free_list_pages[i].page_mut();
+            free_list_page.set_recycled_pages_list(chunk)?;
+            free_list_page.set_chain_free_pages(chain_free_pages)?;
+            chain_free_pages -= chunk.len() as u64;
+        }
+        for free_list_page in free_list_pages.iter_mut() {
+            free_list_page.page_mut().update_checksum();
         }
         Ok(())
     }
 
-    pub fn get_free_list_page_next(&self) -> u64 {
-        let mut value = LittleEndian::read_u64(
-            &self.data[FREE_LIST_PAGE_NEXT_START..FREE_LIST_PAGE_NEXT_START + 8],
-        );
-        // Suponiendo que quieras anular los últimos 2 bytes:
-        let mask = !0u64 >> (8 * 2);
-        value &= mask;
-
-        value
+    // Number of page ids this page can hold
+    pub fn get_capacity(&self) -> usize {
+        free_list_capacity(self.data.len() as u64)
     }
 
-    pub fn set_free_list_page_next(&mut self, value: u64) {
-        let mut buf = [0u8; 8];
-        LittleEndian::write_u64(&mut buf, value);
-
-        self.data[FREE_LIST_PAGE_NEXT_START..FREE_LIST_PAGE_NEXT_START + 6]
-            .copy_from_slice(&buf[0..6]);
-    }
-
-    // Method to set the contents of this FreeListPage with the contents of another FreeListPage.
-    pub fn get_free_pages_list_slice(&self) -> Result<[u64; 510], std::io::Error> {
-        let mut u64_array = [0u64; 510];
-
-        u64_array
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, u64_val)| {
-                let offset = i * std::mem::size_of::<u64>();
-                *u64_val = LittleEndian::read_u64(
-                    &self.data
-                        [DATA_START + offset..DATA_START + offset + std::mem::size_of::<u64>()],
-                );
-            });
-
-        Ok(u64_array)
+    pub fn get_free_pages_list_slice(&self) -> Result<Vec<u64>, MemoryManagerError> {
+        read_free_pages_list_slice(self.data)
     }
     // Method to set the contents of this FreeListPage with the contents of another FreeListPage.
     #[allow(dead_code)]
     pub fn set_free_list_page_header_slice(&mut self, data_slice: &[u8]) {
-        self.data[FREE_LIST_PAGE_NEXT_START..DATA_START].copy_from_slice(data_slice);
+        self.data[..HEADER_BYTES].copy_from_slice(data_slice);
         // Copying the bytes from data_slice into the remaining bytes of data.
     }
     #[allow(dead_code)]
     pub fn copy_free_list_page_header_slice(&mut self, free_list_page: &FreeListPage) {
-        self.data[FREE_LIST_PAGE_NEXT_START..DATA_START]
-            .copy_from_slice(&free_list_page.data[FREE_LIST_PAGE_NEXT_START..DATA_START]);
+        self.data[..HEADER_BYTES].copy_from_slice(&free_list_page.data[..HEADER_BYTES]);
         // Copying the bytes from data_slice into the remaining bytes of data.
     }
     // Method to set the contents of this FreeListPage with the contents of another FreeListPage.
     pub fn set_free_list_page_data_slice(&mut self, data_slice: &[u8]) {
-        self.data[DATA_START..DATA_END].copy_from_slice(&data_slice);
+        let data_end = data_end(self.data.len());
+        self.data[DATA_START..data_end].copy_from_slice(data_slice);
         // Copying the bytes from data_slice into the remaining bytes of data.
     }
-    pub fn get_recycled_pages_list(&self) -> Result<Vec<u64>, std::io::Error> {
-        let mut cursor = Cursor::new(&self.data[DATA_START..DATA_END]);
-        let mut vec = Vec::new();
+    pub fn get_recycled_pages_list(&self) -> Result<Vec<u64>, MemoryManagerError> {
+        read_recycled_pages_list(self.data)
+    }
 
-        while let Ok(num) = cursor.read_u64::<LittleEndian>() {
-            if num != 0 {
-                vec.push(num);
-            }
+    // Stores the page ids, the rest of the entries are cleared. The page is left untouched if
+    // they don't fit in the page or a page id doesn't fit in a U48.
+    pub fn set_recycled_pages_list(&mut self, pages: &[u64]) -> Result<(), MemoryManagerError> {
+        let capacity = self.get_capacity();
+        if pages.len() > capacity {
+            return Err(MemoryManagerError::InvalidInput(format!(
+                "Error: {} pages don't fit in a free list page of {} entries",
+                pages.len(),
+                capacity
+            )));
+        }
+        let entries = pages
+            .iter()
+            .map(|&page| U48::try_from(page))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let data_end = data_end(self.data.len());
+        let data = &mut self.data[DATA_START..data_end];
+        data.fill(0);
+        for (entry, bytes) in entries.iter().zip(data.chunks_exact_mut(ENTRY_BYTES)) {
+            entry.write(bytes);
         }
-        Ok(vec)
+        Ok(())
     }
 }
 
-// Implementing the Debug trait for FreeListPage to enable custom debug formatting.
-impl<'a> fmt::Debug for FreeListPage<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "FreeListPage {{free_list_page_next: {:?} \nget_free_pages_list_slice: {:?} ...}}",
-            self.get_free_list_page_next(),
-            self.get_free_pages_list_slice().unwrap()[0..3].to_vec()
-        )
+impl<'a> FreeListPageRef<'a> {
+    // Number of page ids this page can hold
+    pub fn get_capacity(&self) -> usize {
+        free_list_capacity(self.data.len() as u64)
+    }
+
+    pub fn get_free_pages_list_slice(&self) -> Result<Vec<u64>, MemoryManagerError> {
+        read_free_pages_list_slice(self.data)
+    }
+
+    pub fn get_recycled_pages_list(&self) -> Result<Vec<u64>, MemoryManagerError> {
+        read_recycled_pages_list(self.data)
     }
+
+    // The first page ids, shown by Debug
+    fn first_free_pages(&self) -> Vec<u64> {
+        self.data[DATA_START..data_end(self.data.len())]
+            .chunks_exact(ENTRY_BYTES)
+            .take(3)
+            .map(|entry| U48::read(entry).get())
+            .collect()
+    }
+}
+
+// The readers shared by FreeListPage and FreeListPageRef
+fn read_free_pages_list_slice(data: &[u8]) -> Result<Vec<u64>, MemoryManagerError> {
+    let mut u64_array = vec![0u64; free_list_capacity(data.len() as u64)];
+
+    u64_array
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(i, u64_val)| {
+            let offset = DATA_START + i * ENTRY_BYTES;
+            *u64_val = U48::read(&data[offset..]).get();
+        });
+
+    Ok(u64_array)
+}
+
+fn read_recycled_pages_list(data: &[u8]) -> Result<Vec<u64>, MemoryManagerError> {
+    let vec = data[DATA_START..data_end(data.len())]
+        .chunks_exact(ENTRY_BYTES)
+        .map(|entry| U48::read(entry).get())
+        .filter(|&num| num != 0)
+        .collect();
+    Ok(vec)
 }
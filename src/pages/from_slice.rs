@@ -1,7 +1,3 @@
-use crate::pages::config_page::ConfigPage;
-use crate::pages::free_list_page::FreeListPage;
-use crate::pages::generic_page::GenericPage;
-
 // Defining a trait FromSlice with a lifetime parameter 'a.
 // This trait specifies a single method, from_slice, which takes a mutable reference to a byte slice
 // and returns an instance of the implementing type.
@@ -9,23 +5,27 @@ pub trait FromSlice<'a> {
     fn from_slice(data: &'a mut [u8]) -> Self;
 }
 
-// Definición de la macro
-macro_rules! impl_from_slice {
-    ($type:ident, $lifetime:lifetime) => {
-        // Implementación del trait FromSlice para el tipo proporcionado
-        impl<'a> FromSlice<'a> for $type<$lifetime> {
-            fn from_slice(data: &'a mut [u8]) -> Self {
-                $type { data: data } // Creando una nueva instancia del tipo proporcionado con la slice de bytes proporcionada
-            }
-        }
-    };
+// GenericPage implements it in generic_page, the other pages are declared with page_layout!
+
+// A mutable page view that can lend itself for a shorter lifetime. PageGuard never hands out
+// a &mut to the view it keeps: it could be swapped for another view and outlive the guard.
+// PageGuard::page_mut hands out a reborrowed view instead, which can't outlive the guard.
+pub trait Reborrow {
+    type Target<'b>
+    where
+        Self: 'b;
+
+    fn reborrow(&mut self) -> Self::Target<'_>;
+}
+
+// Read-only counterpart of FromSlice, builds a page view from a shared byte slice.
+// Used by MemoryManager::get_page_ref, which only takes a shared borrow of the page.
+pub trait FromSliceRef<'a> {
+    fn from_slice_ref(data: &'a [u8]) -> Self;
 }
 
-// Uso de la macro para generar las implementaciones del trait FromSlice
-// para GenericPage y ConfigPage
-impl_from_slice!(GenericPage,'a);
-impl_from_slice!(ConfigPage,'a);
-impl_from_slice!(FreeListPage,'a);
+// GenericPageRef implements it in generic_page, the other pages are declared with page_layout!
+
 /*
 // Implementing the FromSlice trait for the GenericPage type.
 // This implementation will allow a GenericPage to be constructed from a mutable byte slice.
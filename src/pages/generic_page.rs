@@ -1,11 +1,56 @@
+use crate::pages::from_slice::{FromSlice, FromSliceRef, Reborrow};
+
 #[derive(Debug, PartialEq)]
 pub struct GenericPage<'a> {
-    pub data: &'a mut [u8],
+    data: &'a mut [u8],
 }
 
 impl<'a> GenericPage<'a> {
     #[allow(dead_code)]
     pub fn from_config_page(data: &'a mut [u8]) -> Self {
-        GenericPage { data: data }
+        GenericPage { data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+}
+
+impl<'a> FromSlice<'a> for GenericPage<'a> {
+    fn from_slice(data: &'a mut [u8]) -> Self {
+        GenericPage { data }
+    }
+}
+
+impl<'a> Reborrow for GenericPage<'a> {
+    type Target<'b>
+        = GenericPage<'b>
+    where
+        Self: 'b;
+
+    fn reborrow(&mut self) -> GenericPage<'_> {
+        GenericPage { data: self.data }
+    }
+}
+
+// Read-only view of a page, what MemoryManager::get_page hands out
+#[derive(Debug, PartialEq)]
+pub struct GenericPageRef<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> GenericPageRef<'a> {
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> FromSliceRef<'a> for GenericPageRef<'a> {
+    fn from_slice_ref(data: &'a [u8]) -> Self {
+        GenericPageRef { data }
     }
 }
@@ -1,8 +1,11 @@
 pub mod generic_page;
+pub mod checksum;
 pub mod config_page;
 pub mod free_list_page;
 pub mod from_slice;
+pub mod page_layout;
 pub mod page_manager;
+pub mod read_only_page_manager;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct Page {
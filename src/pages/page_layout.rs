@@ -0,0 +1,240 @@
+// Declares a page type from the byte layout of its fields, so a new page type doesn't need a
+// pile of offset constants. For every field it generates get_<field> and set_<field>, and for
+// the types that keep a history get_<field>_at.
+//
+//     page_layout! {
+//         pub struct IndexPage, IndexPageRef {
+//             start: 16,
+//             history: check_slot,
+//             debug: [first_keys],
+//             fields {
+//                 root: U48,
+//                 version: U40,
+//             }
+//         }
+//     }
+//
+// IndexPage wraps a mutable page and IndexPageRef a shared one, both get FromSlice (or
+// FromSliceRef), From conversions to the Ref type, Debug and the checksum methods. IndexPage
+// gets Reborrow too, for PageGuard::page_mut. The bytes are private, data() (and data_mut()
+// on IndexPage) lend them. IndexPageRef isn't Copy, so it can't outlive its PageRef. The fields
+// are stored one after the other in a record that starts at byte start, their types are the
+// ones in u48 (anything with BYTES, try_from, read, write and get). Setters take a u64 and
+// refuse the values that don't fit in the field. The record must fit in the smallest page
+// along with the checksum, or the crate doesn't compile.
+//
+// history is optional. With it the page holds as many records as fit before the checksum:
+// the getters and setters use record 0 and get_<field>_at(slot) reads the record in slot,
+// after checking it with the method given (check_slot, generated, only checks the slot is in
+// the page). debug is optional too, the methods of the Ref type listed are shown by Debug
+// after the fields.
+#[macro_export]
+macro_rules! page_layout {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident, $ref_name:ident {
+            start: $start:expr,
+            $(history: $check:ident,)?
+            $(debug: [$($extra:ident),* $(,)?],)?
+            fields {
+                $($field:ident: $ty:ty),+ $(,)?
+            }
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(PartialEq)]
+        $vis struct $name<'a> {
+            data: &'a mut [u8],
+        }
+
+        // Read-only view of the page. It isn't Copy, a copy could outlive the PageRef it
+        // came from.
+        #[derive(PartialEq)]
+        $vis struct $ref_name<'a> {
+            data: &'a [u8],
+        }
+
+        const _: () = assert!(
+            $start + (0 $(+ <$ty>::BYTES)+) + $crate::pages::checksum::CHECKSUM_BYTES
+                <= $crate::storage::MIN_PAGE_SIZE as usize,
+            concat!("The fields of ", stringify!($name), " don't fit in a page")
+        );
+
+        $crate::page_layout!(@common $name, $start, $($ty),+);
+        $crate::page_layout!(@common $ref_name, $start, $($ty),+);
+        $crate::page_layout!(@getters $name, [$($check)?], 0, $($field: $ty,)+);
+        $crate::page_layout!(@getters $ref_name, [$($check)?], 0, $($field: $ty,)+);
+        $crate::page_layout!(@setters $name, 0, $($field: $ty,)+);
+        $(
+            $crate::page_layout!(@history $name, $check);
+            $crate::page_layout!(@history $ref_name, $check);
+        )?
+
+        impl<'a> $name<'a> {
+            // Must be called once the page is written, before it's flushed
+            pub fn update_checksum(&mut self) {
+                $crate::pages::checksum::write_checksum(self.data);
+            }
+
+            pub fn data_mut(&mut self) -> &mut [u8] {
+                self.data
+            }
+        }
+
+        impl<'a> $crate::pages::from_slice::Reborrow for $name<'a> {
+            type Target<'b> = $name<'b> where Self: 'b;
+
+            fn reborrow(&mut self) -> $name<'_> {
+                $name { data: self.data }
+            }
+        }
+
+        impl<'a> $crate::pages::from_slice::FromSlice<'a> for $name<'a> {
+            fn from_slice(data: &'a mut [u8]) -> Self {
+                $name { data }
+            }
+        }
+
+        impl<'a> $crate::pages::from_slice::FromSliceRef<'a> for $ref_name<'a> {
+            fn from_slice_ref(data: &'a [u8]) -> Self {
+                $ref_name { data }
+            }
+        }
+
+        impl<'b> From<&'b $name<'_>> for $ref_name<'b> {
+            fn from(page: &'b $name<'_>) -> Self {
+                $ref_name { data: page.data }
+            }
+        }
+
+        impl<'b> From<&'b $ref_name<'_>> for $ref_name<'b> {
+            fn from(page: &'b $ref_name<'_>) -> Self {
+                $ref_name { data: page.data }
+            }
+        }
+
+        impl<'b> From<&'b $crate::memory_manager::PageGuard<$name<'_>>> for $ref_name<'b> {
+            fn from(page: &'b $crate::memory_manager::PageGuard<$name<'_>>) -> Self {
+                $ref_name::from(&**page)
+            }
+        }
+
+        impl<'b> From<&'b $crate::memory_manager::PageRef<$ref_name<'_>>> for $ref_name<'b> {
+            fn from(page: &'b $crate::memory_manager::PageRef<$ref_name<'_>>) -> Self {
+                $ref_name::from(&**page)
+            }
+        }
+
+        impl<'a> ::std::fmt::Debug for $ref_name<'a> {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                $crate::paste::paste! {
+                    f.debug_struct(stringify!($ref_name))
+                        $(.field(stringify!($field), &self.[<get_ $field>]()))+
+                        $($(.field(stringify!($extra), &self.$extra()))*)?
+                        .finish()
+                }
+            }
+        }
+
+        impl<'a> ::std::fmt::Debug for $name<'a> {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                let page = $ref_name::from(self);
+                $crate::paste::paste! {
+                    f.debug_struct(stringify!($name))
+                        $(.field(stringify!($field), &page.[<get_ $field>]()))+
+                        $($(.field(stringify!($extra), &page.$extra()))*)?
+                        .finish()
+                }
+            }
+        }
+    };
+
+    (@common $type:ident, $start:expr, $($ty:ty),+) => {
+        impl<'a> $type<'a> {
+            // Where the first record starts
+            pub const START: usize = $start;
+            // Bytes taken by the fields
+            pub const RECORD_BYTES: usize = 0 $(+ <$ty>::BYTES)+;
+
+            pub fn data(&self) -> &[u8] {
+                self.data
+            }
+
+            // index is only used to report the error
+            pub fn verify_checksum(
+                &self,
+                index: u64,
+            ) -> Result<(), $crate::error::MemoryManagerError> {
+                $crate::pages::checksum::verify_checksum(self.data, index)
+            }
+
+            // The bytes of the record in slot, from the field at offset on
+            fn record(&self, slot: u64, offset: usize) -> &[u8] {
+                &self.data[Self::START + slot as usize * Self::RECORD_BYTES + offset..]
+            }
+        }
+    };
+
+    (@getters $type:ident, [$($check:ident)?], $offset:expr, $field:ident: $ty:ty, $($rest:tt)*) => {
+        $crate::paste::paste! {
+            impl<'a> $type<'a> {
+                pub fn [<get_ $field>](&self) -> u64 {
+                    <$ty>::read(self.record(0, $offset)).get()
+                }
+                $(
+                    pub fn [<get_ $field _at>](
+                        &self,
+                        slot: u64,
+                    ) -> Result<u64, $crate::error::MemoryManagerError> {
+                        self.$check(slot)?;
+                        Ok(<$ty>::read(self.record(slot, $offset)).get())
+                    }
+                )?
+            }
+        }
+        $crate::page_layout!(@getters $type, [$($check)?], $offset + <$ty>::BYTES, $($rest)*);
+    };
+    (@getters $type:ident, [$($check:ident)?], $offset:expr,) => {};
+
+    (@setters $type:ident, $offset:expr, $field:ident: $ty:ty, $($rest:tt)*) => {
+        $crate::paste::paste! {
+            impl<'a> $type<'a> {
+                // Values that don't fit in the field are refused, the page is left untouched
+                pub fn [<set_ $field>](
+                    &mut self,
+                    value: u64,
+                ) -> Result<(), $crate::error::MemoryManagerError> {
+                    let value = <$ty>::try_from(value)?;
+                    value.write(&mut self.data[Self::START + $offset..]);
+                    Ok(())
+                }
+            }
+        }
+        $crate::page_layout!(@setters $type, $offset + <$ty>::BYTES, $($rest)*);
+    };
+    (@setters $type:ident, $offset:expr,) => {};
+
+    (@history $type:ident, $check:ident) => {
+        impl<'a> $type<'a> {
+            // Number of records that fit in the page, including the current one in slot 0.
+            // The checksum takes the last bytes of the page.
+            pub fn get_history_slots(&self) -> u64 {
+                ((self.data.len() - Self::START - $crate::pages::checksum::CHECKSUM_BYTES)
+                    / Self::RECORD_BYTES) as u64
+            }
+
+            // The slot must be in the page
+            #[allow(dead_code)]
+            pub fn check_slot(&self, slot: u64) -> Result<(), $crate::error::MemoryManagerError> {
+                let max_version = self.get_history_slots() - 1;
+                if slot > max_version {
+                    return Err($crate::error::MemoryManagerError::VersionOutOfRange {
+                        version: slot,
+                        max_version,
+                    });
+                }
+                Ok(())
+            }
+        }
+    };
+}
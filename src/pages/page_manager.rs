@@ -1,70 +1,248 @@
-use crate::logger;
+use crate::error::MemoryManagerError;
 use crate::memory_manager;
-use crate::memory_manager::MemoryManager;
-use crate::pages::config_page::ConfigPage;
-use crate::pages::free_list_page::FreeListPage;
-use byteorder::ByteOrder;
-use byteorder::LittleEndian;
-use slog::{crit, debug, info};
-use std::io::{self, ErrorKind};
+use crate::memory_manager::{Advice, MemoryManager, PageGuard, PageRef};
+use crate::pages::config_page::{self, ConfigPage, ConfigPageRef};
+use crate::pages::free_list_page::{self, FreeListPage, FreeListPageRef};
+use slog::{crit, debug, info, warn, Logger};
+
+mod check;
+mod repair;
+mod vacuum;
+
+pub use check::{CheckIssue, CheckReport};
+
+// How the backing file grows when we run out of fresh pages.
+// max_pages caps the size of the file, None means unbounded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    // Fail with "not enough pages" (the file never grows)
+    #[default]
+    Disabled,
+    // Double the number of pages until the request fits
+    Doubling {
+        max_pages: Option<u64>,
+    },
+    // Add a fixed number of pages until the request fits
+    Increment {
+        pages: u64,
+        max_pages: Option<u64>,
+    },
+}
+
+impl GrowthPolicy {
+    // Returns the new number of pages needed to hold at least required_pages,
+    // or None if the policy doesn't allow growing that much.
+    pub fn next_num_pages(&self, current_pages: u64, required_pages: u64) -> Option<u64> {
+        let max_pages = match *self {
+            GrowthPolicy::Disabled => return None,
+            GrowthPolicy::Doubling { max_pages } => max_pages,
+            GrowthPolicy::Increment { max_pages, .. } => max_pages,
+        };
+        let mut next = current_pages;
+        while next < required_pages {
+            next = match *self {
+                GrowthPolicy::Doubling { .. } => next.max(1).checked_mul(2)?,
+                GrowthPolicy::Increment { pages, .. } if pages > 0 => next.checked_add(pages)?,
+                _ => return None,
+            };
+        }
+        match max_pages {
+            // We can still satisfy the request if the cap is big enough
+            Some(max_pages) if next > max_pages => {
+                (required_pages <= max_pages).then_some(max_pages)
+            }
+            _ => Some(next),
+        }
+    }
+}
+
 pub struct PageManager<'a> {
     memory: &'a mut MemoryManager,
-    pub config_page: ConfigPage<'a>,
+    config_page: PageGuard<ConfigPage<'a>>,
     pub last_used_page: u64,
     pub recycled_pages: Vec<u64>,
     pub recycled_pages_page: u64,
     pub total_allocated_pages: u64,
     pub pending_recycled: Vec<u64>,
+    pub growth_policy: GrowthPolicy,
+    // Taken from the memory manager when the page manager is created
+    logger: Logger,
 }
 
 impl<'a> PageManager<'a> {
-    pub fn new(memory: &'a mut MemoryManager, num_pages: u64) -> Result<Self, std::io::Error> {
-        let log: &slog::Logger = logger::get_logger();
-
-        let config_page =
-            memory.get_page_mut::<ConfigPage>(memory_manager::RESERVED_CONFIG_PAGE_INDEX)?;
+    pub fn new(memory: &'a mut MemoryManager, num_pages: u64) -> Result<Self, MemoryManagerError> {
+        let log = memory.logger().clone();
 
-        // Read the config page
-        info!(log, "Loading config page...");
-        let last_used_page = config_page.get_last_used_page();
-        let recycled_pages_page = config_page.get_recycled_pages_list();
-        let num_pages_config = config_page.get_total_allocated_pages();
-
-        let mut page_manager = PageManager {
-            config_page: config_page,
-            memory: memory,
-            last_used_page: last_used_page,
-            recycled_pages: vec![],
-            recycled_pages_page: recycled_pages_page,
-            total_allocated_pages: num_pages_config,
-            pending_recycled: vec![],
-        };
+        let mut page_manager = Self::load(memory)?;
 
         // Check if the memory is initalized
-        if num_pages_config == 0 {
+        if page_manager.total_allocated_pages == 0 {
             info!(log, "Initializing memory...");
 
             if page_manager.last_used_page != 0 || page_manager.recycled_pages_page != 0 {
-                let err_msg = format!(
+                let err_msg =
                     "Database file is corrupted: last_used_page != 0 || recycled_pages_page != 0"
-                );
+                        .to_string();
                 crit!(log, "{}", &err_msg);
-                return Err(io::Error::new(ErrorKind::Other, err_msg));
+                return Err(MemoryManagerError::Corrupted(err_msg));
             }
             page_manager.total_allocated_pages = num_pages;
+            // The second meta page is used from the second commit on
+            page_manager.last_used_page = memory_manager::FIRST_DATA_PAGE_INDEX - 1;
             page_manager.recycled_pages_page = page_manager.get_free_pages(1, true)?.remove(0);
             page_manager.consolidate_state_initial()?;
         }
 
-        page_manager.recycled_pages = page_manager
-            .memory
-            .get_page_mut::<FreeListPage>(page_manager.recycled_pages_page)?
-            .get_recycled_pages_list()?;
+        page_manager.load_recycled_pages()?;
+
+        debug!(log, "{:?} ", page_manager.config_page);
+        Ok(page_manager)
+    }
+
+    // Opens an already initialized database. The memory must hold at least the
+    // total_allocated_pages stored in the config page, otherwise we refuse to continue instead
+    // of handing out pages that don't exist (or losing the ones that were truncated).
+    // A larger file is what a crash after growing the file leaves behind, before the commit
+    // storing the new size: nothing committed references the pages past the end, they are
+    // ignored like ReadOnlyPageManager does. The file keeps its length until vacuum or growing
+    // resizes it.
+    pub fn open(memory: &'a mut MemoryManager) -> Result<Self, MemoryManagerError> {
+        let log = memory.logger().clone();
+        let file_pages = memory.num_pages();
+
+        let mut page_manager = Self::load(memory)?;
+
+        if page_manager.total_allocated_pages == 0 {
+            let err_msg =
+                "Database file is not initialized: total_allocated_pages == 0".to_string();
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::Corrupted(err_msg));
+        }
+        if page_manager.total_allocated_pages < file_pages {
+            warn!(
+                log,
+                "Ignoring {} pages past total_allocated_pages {}",
+                file_pages - page_manager.total_allocated_pages,
+                page_manager.total_allocated_pages
+            );
+        } else if page_manager.total_allocated_pages > file_pages {
+            let err_msg = format!(
+                "Database size mismatch: total_allocated_pages: {}, pages in file: {}",
+                page_manager.total_allocated_pages, file_pages
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::Corrupted(err_msg));
+        }
+
+        page_manager.load_recycled_pages()?;
 
         debug!(log, "{:?} ", page_manager.config_page);
         Ok(page_manager)
     }
 
+    fn load(memory: &'a mut MemoryManager) -> Result<Self, MemoryManagerError> {
+        let log = memory.logger().clone();
+
+        // Read the config page
+        info!(log, "Loading config page...");
+        let meta_page = find_meta_page(memory, &log)?;
+
+        // The config page lives as long as the page manager. It can't be tied to a borrow of
+        // memory since we hold it mutably, but nobody else can resize it while we hold it and
+        // we reload the page every time we resize it ourselves.
+        let config_page = unsafe { memory.get_page_mut_detached::<ConfigPage>(meta_page)? };
+
+        // Only a database that was never initialized (an in-memory store) has no superblock
+        let total_allocated_pages = config_page.get_total_allocated_pages();
+        if !(config_page::is_blank_header(config_page.data()) && total_allocated_pages == 0) {
+            config_page::check_superblock(config_page.data()).inspect_err(|e| {
+                crit!(log, "Invalid database file header: {}", e);
+            })?;
+        }
+        let page_size = config_page.get_page_size();
+        if page_size != 0 && page_size != memory.page_size() {
+            let err_msg = format!(
+                "Page size mismatch: database page size: {}, memory page size: {}",
+                page_size,
+                memory.page_size()
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::Corrupted(err_msg));
+        }
+        let last_used_page = config_page.get_last_used_page();
+        let recycled_pages_page = config_page.get_recycled_pages_list();
+
+        Ok(PageManager {
+            config_page,
+            memory,
+            last_used_page,
+            recycled_pages: vec![],
+            recycled_pages_page,
+            total_allocated_pages,
+            pending_recycled: vec![],
+            growth_policy: GrowthPolicy::default(),
+            logger: log,
+        })
+    }
+
+    // Every free list page is read through here so a corrupted page is never trusted.
+    // It doesn't borrow the whole page manager, the page can be held while updating it.
+    fn read_free_list_page<'m>(
+        memory: &'m MemoryManager,
+        log: &Logger,
+        index: u64,
+    ) -> Result<PageRef<FreeListPageRef<'m>>, MemoryManagerError> {
+        let free_list_page = memory.get_page_ref::<FreeListPageRef>(index)?;
+        free_list_page.verify_checksum(index).inspect_err(|e| {
+            crit!(log, "{}", e);
+        })?;
+        Ok(free_list_page)
+    }
+
+    fn load_recycled_pages(&mut self) -> Result<(), MemoryManagerError> {
+        let recycled_pages_page =
+            Self::read_free_list_page(self.memory, &self.logger, self.recycled_pages_page)?;
+        self.recycled_pages = recycled_pages_page.get_recycled_pages_list()?;
+        self.prefetch_free_list_page(recycled_pages_page.get_free_list_page_next());
+        self.lock_free_list_page(0)
+    }
+
+    // With MemoryManagerOptions::lock_metadata the free list page we are using stays
+    // resident, called whenever recycled_pages_page changes to move the lock along
+    fn lock_free_list_page(&self, previous_page: u64) -> Result<(), MemoryManagerError> {
+        if !self.memory.locks_metadata() || previous_page == self.recycled_pages_page {
+            return Ok(());
+        }
+        if previous_page != 0 {
+            self.memory.unlock_pages(previous_page, 1)?;
+        }
+        self.memory.lock_pages(self.recycled_pages_page, 1)
+    }
+
+    // Asks the kernel to start reading the next free list page of the chain, so it's already
+    // in memory when we run out of recycled pages
+    fn prefetch_free_list_page(&self, page: u64) {
+        if page == 0 {
+            return;
+        }
+        if let Err(e) = self.memory.advise(page, 1, Advice::WillNeed) {
+            debug!(
+                self.logger,
+                "Failed to prefetch free list page {}: {}", page, e
+            );
+        }
+    }
+
+    // The memory backing this page manager, to read and write the pages it hands out
+    pub fn memory(&self) -> &MemoryManager {
+        self.memory
+    }
+
+    // The meta page holding the current root. Read-only, only commits write it.
+    pub fn config_page(&self) -> &PageGuard<ConfigPage<'a>> {
+        &self.config_page
+    }
+
     #[allow(dead_code)]
     pub fn recyle_pages(&mut self, pending: &mut Vec<u64>) {
         self.pending_recycled.append(pending);
@@ -74,93 +252,79 @@ impl<'a> PageManager<'a> {
         &mut self,
         num: u64,
         reuse_pages: bool,
-    ) -> Result<Vec<u64>, std::io::Error> {
+    ) -> Result<Vec<u64>, MemoryManagerError> {
         let mut free_pages: Vec<u64> = vec![];
 
+        // Every page must be available before we take any of them: failing halfway would lose
+        // the recycled pages taken and leave last_used_page past the end of the file
+        let num_recycled = if !reuse_pages {
+            0
+        } else if self.recycled_pages.len() as u64 >= num {
+            num
+        } else {
+            self.num_recycled_pages()?.min(num)
+        };
+        let required_pages = self.last_used_page + (num - num_recycled) + 1;
+        if num_recycled < num && required_pages > self.total_allocated_pages {
+            self.grow(required_pages)?;
+            if required_pages > self.total_allocated_pages {
+                let err = MemoryManagerError::OutOfPages {
+                    total_allocated_pages: self.total_allocated_pages,
+                    last_used_page: (self.last_used_page + 1).max(self.total_allocated_pages),
+                };
+                crit!(self.logger, "{}", &err);
+                return Err(err);
+            }
+        }
+
         if reuse_pages {
-            // We use recycled pages first
-            if self.recycled_pages.len() != 0 {
-                info!(logger::get_logger(), "Using recycled pages...");
-
-                if num <= self.recycled_pages.len() as u64 {
-                    free_pages = self.recycled_pages.drain(0..num as usize).collect();
-                    debug!(
-                        logger::get_logger(),
-                        "Recycled pages used: {:?}", free_pages
-                    );
+            // We use recycled pages first, walking the chain of free list pages as every one
+            // of them runs out
+            loop {
+                let missing = num as usize - free_pages.len();
+                let taken = missing.min(self.recycled_pages.len());
+                free_pages.extend(self.recycled_pages.drain(0..taken));
+                if free_pages.len() as u64 == num {
+                    debug!(self.logger, "Recycled pages used: {:?}", free_pages);
                     return Ok(free_pages);
-                } else {
-                    info!(
-                        logger::get_logger(),
-                        "Trying to load more recycled pages from memory..."
-                    );
-
-                    let current_recycled_pages_page: FreeListPage<'_> = self
-                        .memory
-                        .get_page_mut::<FreeListPage>(self.recycled_pages_page)
-                        .unwrap();
-
-                    // We need to load more free pages
-                    while num < self.recycled_pages.len() as u64 {
-                        if current_recycled_pages_page.get_free_list_page_next() != 0 {
-                            let previous_recycled_pages_page = self
-                                .memory
-                                .get_page_mut::<FreeListPage>(
-                                    current_recycled_pages_page.get_free_list_page_next(),
-                                )
-                                .unwrap();
-                            if self.recycled_pages.len() != 0 {
-                                let err_msg = format!(
-                                    "Error loading recycled pages: self.recycled_pages.len() != 0"
-                                );
-                                crit!(logger::get_logger(), "{}", &err_msg);
-                                return Err(io::Error::new(ErrorKind::Other, err_msg));
-                            } else {
-                                self.recycled_pages = previous_recycled_pages_page
-                                    .get_recycled_pages_list()
-                                    .unwrap();
-
-                                self.recycled_pages_page =
-                                    current_recycled_pages_page.get_free_list_page_next();
-                            }
-                        } else {
-                            info!(
-                                logger::get_logger(),
-                                "Not enough recycled pages, using new pages..."
-                            );
-                            break;
-                        }
-                    }
-                    if num < self.recycled_pages.len() as u64 {
-                        free_pages = self.recycled_pages.drain(0..num as usize).collect();
-                        debug!(
-                            logger::get_logger(),
-                            "Recycled pages used: {:?}", free_pages
-                        );
-                        return Ok(free_pages);
-                    } else {
-                        free_pages = self
-                            .recycled_pages
-                            .drain(0..self.recycled_pages.len())
-                            .collect();
-                        info!(
-                            logger::get_logger(),
-                            "Not enough recycled pages, using new pages..."
-                        );
-                    }
                 }
+
+                // A database being created has no free list page yet
+                if self.recycled_pages_page == 0 {
+                    break;
+                }
+                debug!(
+                    self.logger,
+                    "Trying to load more recycled pages from memory..."
+                );
+                let next_page =
+                    Self::read_free_list_page(self.memory, &self.logger, self.recycled_pages_page)?
+                        .get_free_list_page_next();
+                if next_page == 0 {
+                    break;
+                }
+                let next_recycled_pages_page =
+                    Self::read_free_list_page(self.memory, &self.logger, next_page)?;
+                self.recycled_pages = next_recycled_pages_page.get_recycled_pages_list()?;
+                self.prefetch_free_list_page(next_recycled_pages_page.get_free_list_page_next());
+                drop(next_recycled_pages_page);
+
+                let previous_page = self.recycled_pages_page;
+                self.recycled_pages_page = next_page;
+                self.lock_free_list_page(previous_page)?;
             }
+            debug!(self.logger, "Not enough recycled pages, using new pages...");
         }
 
         for _ in free_pages.len() as u64..num {
             self.last_used_page += 1;
             if self.last_used_page >= self.total_allocated_pages {
-                let err_msg = format!(
-                    "Error: not enough pages! total_allocated_pages: {}, last_used_page: {}",
-                    self.total_allocated_pages, self.last_used_page
-                );
-                crit!(logger::get_logger(), "{}", &err_msg);
-                return Err(io::Error::new(ErrorKind::Other, err_msg));
+                let err = MemoryManagerError::OutOfPages {
+                    total_allocated_pages: self.total_allocated_pages,
+                    last_used_page: self.last_used_page,
+                };
+                crit!(self.logger, "{}", &err);
+                return Err(err);
             }
             free_pages.push(self.last_used_page);
         }
@@ -168,144 +332,359 @@ impl<'a> PageManager<'a> {
         Ok(free_pages)
     }
 
-    pub fn consolidate_state_initial(&mut self) -> Result<(), std::io::Error> {
-        self.config_page
-            .set_total_allocated_pages(self.total_allocated_pages);
-        self.config_page.set_version_number(1);
+    // Recycled pages left: the ones we hold plus the rest of the chain of free list pages
+    fn num_recycled_pages(&self) -> Result<u64, MemoryManagerError> {
+        let mut num_recycled = self.recycled_pages.len() as u64;
+        // A database being created has no free list page yet
+        if self.recycled_pages_page == 0 {
+            return Ok(num_recycled);
+        }
+        let next_page =
+            Self::read_free_list_page(self.memory, &self.logger, self.recycled_pages_page)?
+                .get_free_list_page_next();
+        if next_page != 0 {
+            num_recycled += Self::read_free_list_page(self.memory, &self.logger, next_page)?
+                .get_chain_free_pages();
+        }
+        Ok(num_recycled)
+    }
 
-        self.config_page.set_last_used_page(self.last_used_page);
+    // Grows the backing file following the growth policy so it can hold required_pages.
+    // If the policy doesn't allow it we leave the file untouched and let the caller fail.
+    fn grow(&mut self, required_pages: u64) -> Result<(), MemoryManagerError> {
+        let log = self.logger.clone();
+
+        let new_total_allocated_pages = match self
+            .growth_policy
+            .next_num_pages(self.total_allocated_pages, required_pages)
+        {
+            Some(pages) => pages,
+            None => {
+                debug!(
+                    log,
+                    "Growth policy {:?} doesn't allow {} pages", self.growth_policy, required_pages
+                );
+                return Ok(());
+            }
+        };
+        info!(
+            log,
+            "Growing from {} to {} pages...", self.total_allocated_pages, new_total_allocated_pages
+        );
 
-        self.config_page
-            .set_recycled_pages_list(self.recycled_pages_page);
-        self.config_page.set_previous_config_page(0);
-        self.config_page.set_offset(1);
+        self.resize_memory(new_total_allocated_pages)
+    }
 
+    // Grows or shrinks the memory to num_pages, the new total_allocated_pages. The file has
+    // been mapped again afterwards, so the config page we hold is pointed to its new location.
+    fn resize_memory(&mut self, num_pages: u64) -> Result<(), MemoryManagerError> {
+        self.memory.resize(num_pages)?;
+        unsafe { self.memory.reload_page(&mut self.config_page)? };
+        self.total_allocated_pages = num_pages;
+        Ok(())
+    }
+
+    // Same phases as consolidate_state: the empty free list first, then the first root
+    pub fn consolidate_state_initial(&mut self) -> Result<(), MemoryManagerError> {
+        // The free list starts empty, but it's still checked when loaded
+        self.memory
+            .get_page_mut::<FreeListPage>(self.recycled_pages_page)?
+            .page_mut()
+            .update_checksum();
+        self.memory.flush()?;
+
+        let mut config_page = self.config_page.page_mut();
+        config_page.init_superblock(self.memory.page_size())?;
+        config_page.set_total_allocated_pages(self.total_allocated_pages)?;
+        config_page.set_version_number(1)?;
+
+        config_page.set_last_used_page(self.last_used_page)?;
+
+        config_page.set_recycled_pages_list(self.recycled_pages_page)?;
+        config_page.set_previous_config_page(0)?;
+        config_page.set_offset(1)?;
+        config_page.update_checksum();
+
+        self.memory.mark_dirty(self.config_page.index());
         self.memory.flush()?;
 
         Ok(())
     }
 
-    pub fn get_free_list_page_at(&self, version: u64) -> Result<Vec<u64>, std::io::Error> {
-        if version > self.config_page.get_version_number() {
-            let err_msg = format!("Error: version > self.config_page.get_version_number()");
-            crit!(logger::get_logger(), "{}", &err_msg);
-            return Err(io::Error::new(ErrorKind::Other, err_msg));
-        }
-        let recycled_pages_list = self.config_page.get_recycled_pages_list_at(version);
+    pub fn get_free_list_page_at(&self, version: u64) -> Result<Vec<u64>, MemoryManagerError> {
+        let recycled_pages_list = self.config_page.get_recycled_pages_list_at(version)?;
         debug!(
-            logger::get_logger(),
+            self.logger,
             "Recycled pages list at version {}: {:?}", version, recycled_pages_list
         );
 
-        let version_recycled_pages_page = self
-            .memory
-            .get_page_mut::<FreeListPage>(recycled_pages_list)
-            .unwrap();
-
-        Ok(version_recycled_pages_page
+        Self::read_free_list_page(self.memory, &self.logger, recycled_pages_list)?
             .get_recycled_pages_list()
-            .unwrap())
     }
 
-    pub fn consolidate_state(&mut self) -> Result<(), std::io::Error> {
-        let log: &slog::Logger = logger::get_logger();
+    // Commits the current state and waits for it to reach the disk. Returns the number of bytes
+    // flushed.
+    //
+    // A commit has two phases, each one ending with a durability barrier (MemoryManager::flush,
+    // msync or fdatasync depending on the store):
+    //   1. Every page written since the last commit: the data pages, the new free list pages,
+    //      the archived config page and the temporal config page holding the new root.
+    //   2. The new root, copied to the meta page the current root isn't using.
+    // The pages written in phase 1 are free in the current root and the meta page written in
+    // phase 2 isn't the current root, so the previous commit is never touched:
+    //   - A crash before phase 2 starts leaves the previous root, whatever was written.
+    //   - A crash during phase 2 may tear the new root. Its checksum doesn't match, so open
+    //     falls back to the previous root, whose pages are all intact.
+    //   - Once phase 2 is done the new root and every page it references are durable.
+    // Growing the file isn't part of the commit: after a crash the file may be larger than the
+    // committed total_allocated_pages, open ignores the pages past it.
+    // Data pages written in place (instead of through pages taken from get_free_pages) are not
+    // covered: they reach the disk in phase 1 whether the commit completes or not.
+    pub fn consolidate_state(&mut self) -> Result<u64, MemoryManagerError> {
+        let next_root = self.write_state()?;
+        let flushed = self.memory.flush()?;
+        self.write_root(next_root)?;
+        Ok(flushed + self.memory.flush()?)
+    }
+
+    // Same as consolidate_state but doesn't wait for the root to reach the disk. Phase 1 still
+    // waits, so a crash can lose the commit but never leaves a root pointing to pages that
+    // weren't written. Returns the number of bytes flushed and scheduled for write back.
+    pub fn consolidate_state_async(&mut self) -> Result<u64, MemoryManagerError> {
+        let next_root = self.write_state()?;
+        let flushed = self.memory.flush()?;
+        self.write_root(next_root)?;
+        Ok(flushed + self.memory.flush_async()?)
+    }
+
+    // Phase 1 of a commit: writes the free list and builds the next root in a temporal config
+    // page, whose index is returned. Nothing the current root references is modified.
+    fn write_state(&mut self) -> Result<u64, MemoryManagerError> {
+        let log = self.logger.clone();
 
-        // Check if we need to store a new free list page
-        let actual_recycled_pages_page: FreeListPage<'_> = self
-            .memory
-            .get_page_mut::<FreeListPage>(self.recycled_pages_page)?;
-        let actual_recycled_pages = actual_recycled_pages_page.get_recycled_pages_list()?;
+        // We reserve every page we need before touching any of them: reserving pages may grow
+        // the file, and growing maps the file again, invalidating the pages we already hold.
 
         // create a temporal config page to copy the data
         let next_page_config = self.get_free_pages(1, true)?.remove(0);
-        let mut config_page_tmp = self.memory.get_page_mut::<ConfigPage>(next_page_config)?;
 
-        // If the offset is 127 = (4096/32 -1 ) we don't have space to store the next header
+        // If the offset is past the last history slot we don't have space to store the next header
         // we create a copy of the config_page and we link to the current one
-        if self.config_page.get_offset() > 127 {
-            let next_page_config_copy = self.get_free_pages(1, true)?.remove(0);
-            let mut config_page_copy = self
-                .memory
-                .get_page_mut::<ConfigPage>(next_page_config_copy)?;
-            config_page_copy.copy_config_page(&self.config_page);
-            config_page_tmp.copy_config_page_header(&self.config_page);
-            config_page_tmp.set_offset(1);
-            config_page_tmp.set_previous_config_page(next_page_config_copy);
-            config_page_tmp.copy_header_to_offset();
-            config_page_tmp.set_offset(config_page_tmp.get_offset() + 1);
-            config_page_tmp.set_version_number(self.config_page.get_version_number() + 1);
-        } else {
-            // copy the data from the current config page to the temporal one
-            config_page_tmp.copy_config_page(&self.config_page);
+        let next_page_config_copy =
+            if self.config_page.get_offset() >= self.config_page.get_history_slots() {
+                Some(self.get_free_pages(1, true)?.remove(0))
+            } else {
+                None
+            };
+
+        // Check if we need to store a new free list page. Read once the pages above are taken,
+        // taking them may have moved us to the next page of the chain.
+        let actual_recycled_pages =
+            Self::read_free_list_page(self.memory, &log, self.recycled_pages_page)?
+                .get_recycled_pages_list()?;
 
-            config_page_tmp.copy_header_to_offset();
-            config_page_tmp.set_offset(self.config_page.get_offset() + 1);
-            config_page_tmp.set_version_number(self.config_page.get_version_number() + 1);
-        }
-
-        // We need to compute how many pages we need to store the recycled pages, taking into account that we're going to add one extra page
-        info!(
+        // we added to the pending recycled pages list the next page config since we can reuse it when the process is done
+        self.pending_recycled.push(next_page_config);
+        debug!(
             log,
             "Recycling {} pages...",
-            self.recycled_pages.len() + self.pending_recycled.len() + 1
+            self.recycled_pages.len() + self.pending_recycled.len()
         );
-        let num_chunks = (self.recycled_pages.len() + self.pending_recycled.len() + 1 + 510) / 511;
-        info!(log, "We need {} pages", num_chunks);
 
-        // we added to the pending recycled pages list the next page config since we can reuse it when the process is done
-        self.pending_recycled
-            .append(&mut [next_page_config].to_vec());
+        // we only create a new page if we need to store the recycled pages
+        // TODO: Probably we can remove this check, although we are saving pages
+        let mut next_recycled_pages = self.recycled_pages.clone();
+        next_recycled_pages.extend(&self.pending_recycled);
+        // The chunk pages are taken before adding the pending pages: the current root still
+        // references them until this commit is durable. Taking them shrinks the free list (or
+        // walks the chain), so we take pages until the ones left fit in them.
+        let capacity = free_list_page::free_list_capacity(self.memory.page_size());
+        let mut chunk_pages = vec![];
+        if actual_recycled_pages != next_recycled_pages {
+            loop {
+                let num_chunks = (self.recycled_pages.len() + self.pending_recycled.len())
+                    .div_ceil(capacity)
+                    .max(1);
+                if chunk_pages.len() >= num_chunks {
+                    break;
+                }
+                chunk_pages
+                    .extend(self.get_free_pages((num_chunks - chunk_pages.len()) as u64, true)?);
+            }
+        }
+        debug!(log, "We need {} pages", chunk_pages.len());
 
         // We combine the recycled pages with the pending recycled pages mainly because we need to store the recycled pages
         //in the next config page and  for that we need to wait until all the pages needes are reserved
         self.recycled_pages.append(&mut self.pending_recycled);
-        self.pending_recycled = vec![];
 
         debug!(log, "Recycled pages: {:?}", self.recycled_pages);
-        // we only create a new page if we need to store the recycled pages
-        // TODO: Probably we can remove this check, although we are saving pages
-        if actual_recycled_pages != self.recycled_pages {
-            let chunk_pages = self.get_free_pages(num_chunks as u64, true)?;
-            for (i, chunk) in self.recycled_pages.chunks(510).enumerate() {
-                let actual_recycled_pages_page: FreeListPage<'_> = self
-                    .memory
-                    .get_page_mut::<FreeListPage>(self.recycled_pages_page)?;
-
-                debug!(
-                    log,
-                    "Previous recycled pages page: {:?}",
-                    actual_recycled_pages_page.get_free_list_page_next()
-                );
 
-                let mut current_recycled_pages_page: FreeListPage<'_> =
-                    self.memory.get_page_mut::<FreeListPage>(chunk_pages[i])?;
+        let previous_recycled_pages_page = self.recycled_pages_page;
+        if !chunk_pages.is_empty() {
+            // The new chunks replace the page we are using, the rest of the chain is kept
+            let rest = Self::read_free_list_page(self.memory, &log, self.recycled_pages_page)?
+                .get_free_list_page_next();
+            self.write_free_list_chain(&self.recycled_pages, &chunk_pages, rest)?;
+            self.recycled_pages_page = chunk_pages[0];
+            // We hold the head of the chain, the other chunks are loaded as it runs out
+            self.recycled_pages.truncate(capacity);
+        }
 
-                current_recycled_pages_page
-                    .set_free_list_page_next(actual_recycled_pages_page.get_free_list_page_next());
+        self.lock_free_list_page(previous_recycled_pages_page)?;
 
-                let mut chunk_vec = chunk.to_vec(); // Resize to have length of 510, fill with zeros
-                chunk_vec.resize(510, 0u64);
+        self.write_next_config_page(next_page_config, next_page_config_copy)?;
+        Ok(next_page_config)
+    }
 
-                let mut bytes = Vec::with_capacity(chunk_vec.len() * 8); // 8 bytes per u64
-                for &value in &chunk_vec {
-                    let mut buffer = [0u8; 8];
-                    LittleEndian::write_u64(&mut buffer, value);
-                    bytes.extend_from_slice(&buffer);
-                }
+    // Writes pages to the free list pages in chunk_pages, as many as fit in each. The first one
+    // heads the chain and the last one links to next, the rest of the chain, which is kept.
+    // Every page stores how many free pages the chain holds from it on.
+    fn write_free_list_chain(
+        &self,
+        pages: &[u64],
+        chunk_pages: &[u64],
+        next: u64,
+    ) -> Result<(), MemoryManagerError> {
+        let capacity = free_list_page::free_list_capacity(self.memory.page_size());
+        let chunks: Vec<&[u64]> = pages.chunks(capacity).collect();
+        if chunks.len() > chunk_pages.len() {
+            let err_msg = format!(
+                "Error: {} free pages don't fit in {} free list pages",
+                pages.len(),
+                chunk_pages.len()
+            );
+            crit!(self.logger, "{}", &err_msg);
+            return Err(MemoryManagerError::InvalidInput(err_msg));
+        }
 
-                current_recycled_pages_page.set_free_list_page_data_slice(&bytes);
-                self.recycled_pages_page = chunk_pages[i];
-            }
+        let mut chain_free_pages = if next == 0 {
+            0
+        } else {
+            Self::read_free_list_page(self.memory, &self.logger, next)?.get_chain_free_pages()
+        };
+        let mut next = next;
+        for (i, &chunk_page) in chunk_pages.iter().enumerate().rev() {
+            let chunk = chunks.get(i).copied().unwrap_or_default();
+            chain_free_pages += chunk.len() as u64;
+            let mut guard = self.memory.get_page_mut::<FreeListPage>(chunk_page)?;
+            let mut free_list_page = guard.page_mut();
+            free_list_page.set_free_list_page_next(next)?;
+            free_list_page.set_chain_free_pages(chain_free_pages)?;
+            free_list_page.set_recycled_pages_list(chunk)?;
+            free_list_page.update_checksum();
+            next = chunk_page;
         }
+        Ok(())
+    }
 
-        config_page_tmp.set_last_used_page(self.last_used_page);
-        config_page_tmp.set_recycled_pages_list(self.recycled_pages_page);
-        config_page_tmp.set_total_allocated_pages(self.total_allocated_pages);
+    // Builds the next root in the temporal config page: the current history plus the current
+    // header, with the state we hold now. When the history is full the current config page is
+    // archived in next_page_config_copy and the new history links to it.
+    fn write_next_config_page(
+        &self,
+        next_page_config: u64,
+        next_page_config_copy: Option<u64>,
+    ) -> Result<(), MemoryManagerError> {
+        let mut tmp_guard = self.memory.get_page_mut::<ConfigPage>(next_page_config)?;
+        let mut config_page_tmp = tmp_guard.page_mut();
+        if let Some(next_page_config_copy) = next_page_config_copy {
+            let mut copy_guard = self
+                .memory
+                .get_page_mut::<ConfigPage>(next_page_config_copy)?;
+            let mut config_page_copy = copy_guard.page_mut();
+            config_page_copy.copy_config_page(&self.config_page);
+            config_page_copy.update_checksum();
+            config_page_tmp.copy_config_page_header(&self.config_page);
+            config_page_tmp.set_offset(1)?;
+            config_page_tmp.set_previous_config_page(next_page_config_copy)?;
+            config_page_tmp.copy_header_to_offset();
+            let offset = config_page_tmp.get_offset();
+            config_page_tmp.set_offset(offset + 1)?;
+            config_page_tmp.set_version_number(self.config_page.get_version_number() + 1)?;
+        } else {
+            // copy the data from the current config page to the temporal one
+            config_page_tmp.copy_config_page(&self.config_page);
 
-        // copy the data from the temporal config page to the current one
-        self.config_page.copy_config_page(&config_page_tmp);
-        self.memory.flush()?;
+            config_page_tmp.copy_header_to_offset();
+            config_page_tmp.set_offset(self.config_page.get_offset() + 1)?;
+            config_page_tmp.set_version_number(self.config_page.get_version_number() + 1)?;
+        }
 
+        config_page_tmp.set_last_used_page(self.last_used_page)?;
+        config_page_tmp.set_recycled_pages_list(self.recycled_pages_page)?;
+        config_page_tmp.set_total_allocated_pages(self.total_allocated_pages)?;
         Ok(())
     }
+
+    // Phase 2 of a commit: copies the root built by write_state to the meta page we are not
+    // using, the current one stays untouched until the next commit
+    fn write_root(&mut self, next_root: u64) -> Result<(), MemoryManagerError> {
+        let config_page_tmp = self.memory.get_page_ref::<ConfigPageRef>(next_root)?;
+        let mut next_config_page = unsafe {
+            self.memory
+                .get_page_mut_detached::<ConfigPage>(self.next_meta_page())?
+        };
+        let mut config_page = next_config_page.page_mut();
+        config_page.copy_config_page(&config_page_tmp);
+        config_page.update_checksum();
+        self.config_page = next_config_page;
+        Ok(())
+    }
+
+    // The meta page the next commit is written to
+    fn next_meta_page(&self) -> u64 {
+        let [first, second] = memory_manager::META_PAGE_INDEXES;
+        if self.config_page.index() == first {
+            second
+        } else {
+            first
+        }
+    }
+}
+
+// Picks the meta page holding the current root: the one with the newest version among the
+// ones that pass the superblock and checksum checks. The other one holds the previous commit,
+// or a commit torn by a crash.
+// If none of them was ever committed we return the first one, to be initialized.
+pub(crate) fn find_meta_page(
+    memory: &MemoryManager,
+    log: &Logger,
+) -> Result<u64, MemoryManagerError> {
+    let mut newest: Option<(u64, u64)> = None;
+    let mut error = None;
+    for index in memory_manager::META_PAGE_INDEXES {
+        if index >= memory.num_pages() {
+            break;
+        }
+        let config_page = memory.get_page_ref::<ConfigPageRef>(index)?;
+        // Never committed
+        if config_page.get_total_allocated_pages() == 0 {
+            continue;
+        }
+        let version = config_page.get_version_number();
+        match config_page::check_superblock(config_page.data())
+            .and_then(|_| config_page.verify_checksum(index))
+        {
+            Ok(()) if newest.is_none_or(|(newest_version, _)| version > newest_version) => {
+                newest = Some((version, index));
+            }
+            Ok(()) => {}
+            Err(e) => {
+                warn!(log, "Ignoring meta page {}: {}", index, e);
+                error.get_or_insert(e);
+            }
+        }
+    }
+    match (newest, error) {
+        (Some((version, index)), _) => {
+            debug!(log, "Using meta page {} (version {})", index, version);
+            Ok(index)
+        }
+        (None, Some(e)) => {
+            crit!(log, "No valid meta page: {}", e);
+            Err(e)
+        }
+        (None, None) => Ok(memory_manager::RESERVED_CONFIG_PAGE_INDEX),
+    }
 }
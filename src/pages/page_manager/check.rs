@@ -0,0 +1,299 @@
+use super::PageManager;
+use crate::error::MemoryManagerError;
+use crate::pages::config_page::{ConfigPageRef, MemoryLayout};
+use crate::pages::free_list_page::FreeListPageRef;
+use std::collections::BTreeSet;
+use std::fmt;
+
+// Something wrong found by PageManager::check. version is the newest version whose free list
+// holds the problem, older versions sharing the same free list page are not reported again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckIssue {
+    // The page is listed more than once in the free list
+    DuplicateFreePage {
+        version: u64,
+        page: u64,
+    },
+    // A free page that was never handed out: pages past last_used_page are free already
+    FreePageNotUsed {
+        version: u64,
+        page: u64,
+        last_used_page: u64,
+    },
+    // A free page past the end of the database
+    FreePageOutOfBounds {
+        version: u64,
+        page: u64,
+        total_allocated_pages: u64,
+    },
+    // A page of the free list chain is listed as free, in the same chain
+    FreeListPageListedAsFree {
+        version: u64,
+        page: u64,
+    },
+    // free_list_page_next leads back to a page already in the chain
+    FreeListCycle {
+        version: u64,
+        page: u64,
+    },
+    // The head of the free list says the chain holds a number of free pages, walking it finds
+    // another: part of the chain can't be reached
+    FreeListCountMismatch {
+        version: u64,
+        page: u64,
+        expected: u64,
+        found: u64,
+    },
+    // previous_config_page leads back to a config page already visited
+    ConfigCycle {
+        page: u64,
+    },
+    // The page can't be read or doesn't pass its checksum, the chain stops there
+    UnreadablePage {
+        page: u64,
+        error: String,
+    },
+}
+
+impl fmt::Display for CheckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckIssue::DuplicateFreePage { version, page } => {
+                write!(f, "Version {}: page {} is free twice", version, page)
+            }
+            CheckIssue::FreePageNotUsed {
+                version,
+                page,
+                last_used_page,
+            } => write!(
+                f,
+                "Version {}: free page {} is past last_used_page {}",
+                version, page, last_used_page
+            ),
+            CheckIssue::FreePageOutOfBounds {
+                version,
+                page,
+                total_allocated_pages,
+            } => write!(
+                f,
+                "Version {}: free page {} is past total_allocated_pages {}",
+                version, page, total_allocated_pages
+            ),
+            CheckIssue::FreeListPageListedAsFree { version, page } => write!(
+                f,
+                "Version {}: free list page {} is listed as free",
+                version, page
+            ),
+            CheckIssue::FreeListCycle { version, page } => write!(
+                f,
+                "Version {}: the free list chain loops back to page {}",
+                version, page
+            ),
+            CheckIssue::FreeListCountMismatch {
+                version,
+                page,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Version {}: free list page {} heads {} free pages, the chain holds {}",
+                version, page, expected, found
+            ),
+            CheckIssue::ConfigCycle { page } => {
+                write!(f, "The config chain loops back to page {}", page)
+            }
+            CheckIssue::UnreadablePage { page, error } => {
+                write!(f, "Page {} can't be read: {}", page, error)
+            }
+        }
+    }
+}
+
+// Result of PageManager::check
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    // Versions found in the config history, archived pages included
+    pub versions_checked: u64,
+    // Distinct free list pages walked
+    pub free_list_pages_checked: u64,
+    pub issues: Vec<CheckIssue>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl<'a> PageManager<'a> {
+    // Checks the allocation metadata of every committed version: the free list of each version
+    // stored in the config history, following previous_config_page through the archived config
+    // pages. Only what has been committed is checked, the pages recycled since the last
+    // consolidate_state are not. The number of free pages found walking a chain is compared
+    // with the one stored in its head, so pages the chain no longer reaches are reported too.
+    //
+    // Problems in the database are reported, not returned as errors: an error means the check
+    // itself couldn't run.
+    pub fn check(&self) -> Result<CheckReport, MemoryManagerError> {
+        let mut report = CheckReport::default();
+        // The same free list is usually shared by many versions, each one is walked once per
+        // set of bounds
+        let mut checked_free_lists = BTreeSet::new();
+        let mut free_list_pages = BTreeSet::new();
+        // The current header is stored in slot 0 and in the history, archived pages too
+        let mut versions = BTreeSet::new();
+
+        let mut config_page = self.config_page.index();
+        let mut visited = BTreeSet::from([config_page]);
+        loop {
+            let archived_page;
+            let page: ConfigPageRef = if config_page == self.config_page.index() {
+                ConfigPageRef::from(&self.config_page)
+            } else {
+                archived_page = match self.memory.get_page_ref::<ConfigPageRef>(config_page) {
+                    Ok(page) => page,
+                    Err(e) => {
+                        report.issues.push(CheckIssue::UnreadablePage {
+                            page: config_page,
+                            error: e.to_string(),
+                        });
+                        break;
+                    }
+                };
+                if let Err(e) = archived_page.verify_checksum(config_page) {
+                    report.issues.push(CheckIssue::UnreadablePage {
+                        page: config_page,
+                        error: e.to_string(),
+                    });
+                    break;
+                }
+                ConfigPageRef::from(&archived_page)
+            };
+
+            let num_slots = page
+                .get_offset()
+                .min(page.get_version_number() + 1)
+                .min(page.get_history_slots());
+            for slot in 0..num_slots {
+                let layout = MemoryLayout::from_bytes_at(&page, slot)?;
+                if layout.recycled_pages_list == 0 {
+                    continue;
+                }
+                versions.insert(layout.version_number);
+                if checked_free_lists.insert((
+                    layout.recycled_pages_list,
+                    layout.last_used_page,
+                    layout.total_allocated_pages,
+                )) {
+                    self.check_free_list(&layout, &mut free_list_pages, &mut report);
+                }
+            }
+
+            config_page = page.get_previous_config_page();
+            if config_page == 0 {
+                break;
+            }
+            if !visited.insert(config_page) {
+                report
+                    .issues
+                    .push(CheckIssue::ConfigCycle { page: config_page });
+                break;
+            }
+        }
+
+        report.versions_checked = versions.len() as u64;
+        report.free_list_pages_checked = free_list_pages.len() as u64;
+        Ok(report)
+    }
+
+    // Walks the free list chain of one version
+    fn check_free_list(
+        &self,
+        layout: &MemoryLayout,
+        free_list_pages: &mut BTreeSet<u64>,
+        report: &mut CheckReport,
+    ) {
+        let version = layout.version_number;
+        let mut chain = BTreeSet::new();
+        let mut free_pages = BTreeSet::new();
+        // Stored in the head of the chain, compared once the whole chain has been walked
+        let mut expected = None;
+        let mut found = 0;
+
+        let mut page = layout.recycled_pages_list;
+        while page != 0 {
+            if !chain.insert(page) {
+                report
+                    .issues
+                    .push(CheckIssue::FreeListCycle { version, page });
+                expected = None;
+                break;
+            }
+            free_list_pages.insert(page);
+            let read =
+                self.memory
+                    .get_page_ref::<FreeListPageRef>(page)
+                    .and_then(|free_list_page| {
+                        free_list_page.verify_checksum(page)?;
+                        Ok((
+                            free_list_page.get_free_list_page_next(),
+                            free_list_page.get_chain_free_pages(),
+                            free_list_page.get_recycled_pages_list()?,
+                        ))
+                    });
+            let (next, chain_free_pages, pages) = match read {
+                Ok(result) => result,
+                Err(e) => {
+                    report.issues.push(CheckIssue::UnreadablePage {
+                        page,
+                        error: e.to_string(),
+                    });
+                    // The count can't be compared with a chain we couldn't walk
+                    expected = None;
+                    break;
+                }
+            };
+            expected.get_or_insert(chain_free_pages);
+            found += pages.len() as u64;
+
+            for free_page in pages {
+                if !free_pages.insert(free_page) {
+                    report.issues.push(CheckIssue::DuplicateFreePage {
+                        version,
+                        page: free_page,
+                    });
+                }
+                if free_page >= layout.total_allocated_pages {
+                    report.issues.push(CheckIssue::FreePageOutOfBounds {
+                        version,
+                        page: free_page,
+                        total_allocated_pages: layout.total_allocated_pages,
+                    });
+                } else if free_page > layout.last_used_page {
+                    report.issues.push(CheckIssue::FreePageNotUsed {
+                        version,
+                        page: free_page,
+                        last_used_page: layout.last_used_page,
+                    });
+                }
+            }
+            page = next;
+        }
+
+        if let Some(expected) = expected.filter(|&expected| expected != found) {
+            report.issues.push(CheckIssue::FreeListCountMismatch {
+                version,
+                page: layout.recycled_pages_list,
+                expected,
+                found,
+            });
+        }
+
+        // Checked once the whole chain is known, the page may be listed before it's reached
+        for &page in chain.intersection(&free_pages) {
+            report
+                .issues
+                .push(CheckIssue::FreeListPageListedAsFree { version, page });
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use super::PageManager;
+use crate::error::MemoryManagerError;
+use crate::memory_manager::{self, MemoryManager};
+use crate::pages::free_list_page;
+use slog::{crit, info};
+
+impl<'a> PageManager<'a> {
+    // Rebuilds the free list when it can't be trusted any more (see check), from a scan of the
+    // pages that are in use instead of from the free list itself.
+    //
+    // visit is called once with the memory and a function to mark pages as reachable, it must
+    // mark every page the caller still uses. The allocator metadata that can still be read is
+    // kept as well: the meta pages, the archived config pages and the free list pages reached
+    // from the config history. Every other page up to last_used_page is free: a fresh free list
+    // chain is written with them and committed as a new version, the same way consolidate_state
+    // does, so a crash leaves either the previous version or the repaired one.
+    // The pages recycled since the last commit are dropped, they are free if nothing reaches them.
+    // The older versions keep their free lists, check keeps reporting the damaged ones until
+    // vacuum starts a fresh history.
+    //
+    // Returns the number of free pages in the new free list.
+    pub fn repair<F>(&mut self, visit: F) -> Result<u64, MemoryManagerError>
+    where
+        F: FnOnce(&MemoryManager, &mut dyn FnMut(u64)) -> Result<(), MemoryManagerError>,
+    {
+        let log = self.logger.clone();
+        info!(log, "Repairing the free list...");
+
+        let mut used_pages = self.metadata_pages();
+        visit(self.memory, &mut |page| {
+            used_pages.insert(page);
+        })?;
+        if let Some(&page) = used_pages.range(self.total_allocated_pages..).next() {
+            let err_msg = format!(
+                "Error: reachable page {} is past total_allocated_pages {}",
+                page, self.total_allocated_pages
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::InvalidInput(err_msg));
+        }
+        let mut free_pages: Vec<u64> = (memory_manager::FIRST_DATA_PAGE_INDEX
+            ..=self.last_used_page)
+            .filter(|page| !used_pages.contains(page))
+            .collect();
+        self.recycled_pages = vec![];
+        self.pending_recycled = vec![];
+
+        // Every page we write is taken before writing any, fresh pages may grow the file
+        let next_page_config = self.take_page(&mut free_pages)?;
+        let next_page_config_copy =
+            if self.config_page.get_offset() >= self.config_page.get_history_slots() {
+                Some(self.take_page(&mut free_pages)?)
+            } else {
+                None
+            };
+        // The temporal config page is free once the commit is done, like in consolidate_state
+        let capacity = free_list_page::free_list_capacity(self.memory.page_size());
+        let num_chunks = (free_pages.len() + 1).div_ceil(capacity + 1).max(1);
+        let mut chunk_pages = vec![];
+        for _ in 0..num_chunks {
+            chunk_pages.push(self.take_page(&mut free_pages)?);
+        }
+        free_pages.push(next_page_config);
+        free_pages.sort_unstable();
+
+        // The first chunk holds the lowest pages and heads the chain
+        self.write_free_list_chain(&free_pages, &chunk_pages, 0)?;
+        let previous_recycled_pages_page = self.recycled_pages_page;
+        self.recycled_pages_page = chunk_pages[0];
+        self.write_next_config_page(next_page_config, next_page_config_copy)?;
+
+        // Same phases as consolidate_state
+        self.memory.flush()?;
+        self.write_root(next_page_config)?;
+        self.memory.flush()?;
+
+        // We hold the head of the chain, as if we had just opened the database
+        self.recycled_pages = Self::read_free_list_page(self.memory, &log, chunk_pages[0])?
+            .get_recycled_pages_list()?;
+        self.lock_free_list_page(previous_recycled_pages_page)?;
+
+        info!(
+            log,
+            "Repair done: {} free pages in {} free list pages",
+            free_pages.len(),
+            chunk_pages.len()
+        );
+        Ok(free_pages.len() as u64)
+    }
+
+    // The lowest free page, the current root doesn't reference it so it can be written right
+    // away. Once they are exhausted we take fresh pages.
+    fn take_page(&mut self, free_pages: &mut Vec<u64>) -> Result<u64, MemoryManagerError> {
+        if free_pages.is_empty() {
+            Ok(self.get_free_pages(1, false)?.remove(0))
+        } else {
+            Ok(free_pages.remove(0))
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use super::PageManager;
+use crate::error::MemoryManagerError;
+use crate::memory_manager::{self, MemoryManager};
+use crate::pages::config_page::{ConfigPage, ConfigPageRef, FILE_HEADER_BYTES};
+use crate::pages::free_list_page::{self, FreeListPageRef};
+use crate::pages::generic_page::GenericPage;
+use slog::{crit, debug, info, warn};
+use std::collections::BTreeSet;
+
+impl<'a> PageManager<'a> {
+    // Shrinks the file by moving the live pages at the tail of the file into the lowest free pages.
+    //
+    // Every page below last_used_page that is neither free nor allocator metadata (free list pages,
+    // archived config pages) is considered live. Once all the pages have been copied, relocate is
+    // called with (old_page, new_page) for every moved page so the caller can fix its references.
+    // The config history and the free list are then rewritten from scratch (a single version whose
+    // free list holds the pages below the new end that are still free) and the file is truncated
+    // spare_pages after the last used page.
+    // Committing needs fresh pages, so unless the growth policy allows growing again
+    // spare_pages should leave room for a few consolidate_state calls.
+    //
+    // The new root is committed like in consolidate_state and nothing the last commit references
+    // is overwritten before: the pages moved into are free in it, its metadata pages and the pages
+    // recycled since then are kept where they are (and freed by the new free list). A crash before
+    // the new root is durable leaves the last commit readable. The references relocate fixes are
+    // the caller's, if it writes them in place they are not covered.
+    //
+    // Returns the number of pages released.
+    pub fn vacuum<F>(
+        &mut self,
+        spare_pages: u64,
+        mut relocate: F,
+    ) -> Result<u64, MemoryManagerError>
+    where
+        F: FnMut(&MemoryManager, u64, u64) -> Result<(), MemoryManagerError>,
+    {
+        let log = self.logger.clone();
+        info!(log, "Vacuuming...");
+
+        // Pages the last commit still references even if we no longer use them
+        let mut kept = self.metadata_pages();
+        kept.extend(self.pending_recycled.iter().copied());
+        let mut reclaimable = self.free_pages()?;
+        reclaimable.extend(kept.iter().copied());
+
+        let first_page = memory_manager::FIRST_DATA_PAGE_INDEX;
+        let live_pages: Vec<u64> = (first_page..=self.last_used_page)
+            .filter(|page| !reclaimable.contains(page))
+            .collect();
+        // Pages we can write right away, the ones past last_used_page were never handed out
+        let is_hole = |page: u64| {
+            page > self.last_used_page || (reclaimable.contains(&page) && !kept.contains(&page))
+        };
+
+        // Everything is laid out before writing a page, the live pages past end go to the holes
+        // before it, lowest first. The holes left hold the new free list, which takes the kept
+        // pages and the holes left below end.
+        let capacity = free_list_page::free_list_capacity(self.memory.page_size());
+        let mut end = first_page + live_pages.len() as u64;
+        let (mut holes, moved_pages, num_chunks) = loop {
+            if end > self.total_allocated_pages {
+                let err_msg = format!(
+                    "Error: not enough pages to vacuum! total_allocated_pages: {}, required: {}",
+                    self.total_allocated_pages,
+                    end + spare_pages
+                );
+                crit!(log, "{}", &err_msg);
+                return Err(MemoryManagerError::InvalidInput(err_msg));
+            }
+            let holes: Vec<u64> = (first_page..end).filter(|&page| is_hole(page)).collect();
+            let moved_pages: Vec<u64> = live_pages
+                .iter()
+                .copied()
+                .filter(|&page| page >= end)
+                .collect();
+            if holes.len() > moved_pages.len() {
+                let holes_left = holes.len() - moved_pages.len();
+                let free_pages = kept.range(first_page..end).count() + holes_left;
+                // Every chunk page stores up to capacity pages, plus itself
+                let num_chunks = free_pages.div_ceil(capacity + 1).max(1);
+                if holes_left >= num_chunks {
+                    break (holes, moved_pages, num_chunks);
+                }
+            }
+            end += 1;
+        };
+        let total_allocated_pages = end + spare_pages;
+        if total_allocated_pages > self.total_allocated_pages {
+            let err_msg = format!(
+                "Error: not enough pages to vacuum! total_allocated_pages: {}, required: {}",
+                self.total_allocated_pages, total_allocated_pages
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::InvalidInput(err_msg));
+        }
+        let holes_left = holes.split_off(moved_pages.len());
+        let moves: Vec<(u64, u64)> = moved_pages.into_iter().zip(holes).collect();
+        let chunk_pages = &holes_left[..num_chunks];
+        let mut free_pages: Vec<u64> = kept.range(first_page..end).copied().collect();
+        free_pages.extend(&holes_left[num_chunks..]);
+        free_pages.sort_unstable();
+
+        for &(old_page, new_page) in &moves {
+            debug!(log, "Moving page {} to {}", old_page, new_page);
+            let source = self.memory.get_page_mut::<GenericPage>(old_page)?;
+            let mut destination = self.memory.get_page_mut::<GenericPage>(new_page)?;
+            destination
+                .page_mut()
+                .data_mut()
+                .copy_from_slice(source.data());
+        }
+        for &(old_page, new_page) in &moves {
+            relocate(self.memory, old_page, new_page)?;
+        }
+
+        self.write_free_list_chain(&free_pages, chunk_pages, 0)?;
+        // Same phases as consolidate_state, the new root goes last
+        self.memory.flush()?;
+
+        // Start a fresh history, the previous versions point to pages that no longer exist
+        let version_number = self.config_page.get_version_number() + 1;
+        let mut guard = unsafe {
+            self.memory
+                .get_page_mut_detached::<ConfigPage>(self.next_meta_page())?
+        };
+        let mut config_page = guard.page_mut();
+        // The superblock is kept as it is
+        config_page.data_mut()[..FILE_HEADER_BYTES]
+            .copy_from_slice(&self.config_page.data()[..FILE_HEADER_BYTES]);
+        config_page.data_mut()[FILE_HEADER_BYTES..].fill(0);
+        config_page.set_total_allocated_pages(total_allocated_pages)?;
+        config_page.set_version_number(version_number)?;
+        config_page.set_last_used_page(end - 1)?;
+        config_page.set_recycled_pages_list(chunk_pages[0])?;
+        config_page.set_previous_config_page(0)?;
+        config_page.set_offset(1)?;
+        config_page.update_checksum();
+        self.config_page = guard;
+        self.memory.flush()?;
+
+        let released_pages = self.total_allocated_pages - total_allocated_pages;
+        self.resize_memory(total_allocated_pages)?;
+        self.last_used_page = end - 1;
+        let previous_recycled_pages_page = self.recycled_pages_page;
+        self.recycled_pages_page = chunk_pages[0];
+        self.lock_free_list_page(previous_recycled_pages_page)?;
+        // We hold the head of the chain, as if we had just opened the database
+        free_pages.truncate(capacity);
+        self.recycled_pages = free_pages;
+        self.pending_recycled = vec![];
+
+        info!(
+            log,
+            "Vacuum done: {} pages moved, {} pages released",
+            moves.len(),
+            released_pages
+        );
+        Ok(released_pages)
+    }
+
+    // Pages that are free right now: the ones we hold in memory, the pending ones and the ones
+    // stored in the rest of the free list chain.
+    fn free_pages(&self) -> Result<BTreeSet<u64>, MemoryManagerError> {
+        let mut free_pages: BTreeSet<u64> = self.recycled_pages.iter().copied().collect();
+        free_pages.extend(self.pending_recycled.iter().copied());
+
+        let mut visited = BTreeSet::new();
+        let mut next =
+            Self::read_free_list_page(self.memory, &self.logger, self.recycled_pages_page)?
+                .get_free_list_page_next();
+        while next != 0 && visited.insert(next) {
+            let free_list_page = Self::read_free_list_page(self.memory, &self.logger, next)?;
+            free_pages.extend(free_list_page.get_recycled_pages_list()?);
+            next = free_list_page.get_free_list_page_next();
+        }
+        Ok(free_pages)
+    }
+
+    // Pages owned by the allocator itself: the meta pages, the free list pages of every version
+    // in the config history and the archived config pages.
+    // A chain stops at the first page that can't be read (see repair), the pages after it are
+    // lost. The damaged page itself is kept, a version still references it.
+    pub(super) fn metadata_pages(&self) -> BTreeSet<u64> {
+        let mut metadata_pages: BTreeSet<u64> =
+            memory_manager::META_PAGE_INDEXES.into_iter().collect();
+        self.add_free_list_chain(self.recycled_pages_page, &mut metadata_pages);
+
+        let mut config_page = self.config_page.index();
+        loop {
+            // We already hold the current config page
+            let archived_page;
+            let page: ConfigPageRef = if config_page == self.config_page.index() {
+                ConfigPageRef::from(&self.config_page)
+            } else {
+                archived_page = match self
+                    .memory
+                    .get_page_ref::<ConfigPageRef>(config_page)
+                    .and_then(|page| page.verify_checksum(config_page).map(|_| page))
+                {
+                    Ok(page) => page,
+                    Err(e) => {
+                        warn!(
+                            self.logger,
+                            "Config chain lost at page {}: {}", config_page, e
+                        );
+                        break;
+                    }
+                };
+                ConfigPageRef::from(&archived_page)
+            };
+            let num_slots = page
+                .get_offset()
+                .min(page.get_version_number() + 1)
+                .min(page.get_history_slots());
+            for slot in 0..num_slots {
+                let recycled_pages_list = page.get_recycled_pages_list_at(slot).unwrap_or(0);
+                self.add_free_list_chain(recycled_pages_list, &mut metadata_pages);
+            }
+            config_page = page.get_previous_config_page();
+            if config_page == 0
+                || config_page >= self.total_allocated_pages
+                || !metadata_pages.insert(config_page)
+            {
+                break;
+            }
+        }
+        metadata_pages
+    }
+
+    fn add_free_list_chain(&self, mut page: u64, pages: &mut BTreeSet<u64>) {
+        while page != 0 && page <= self.last_used_page && pages.insert(page) {
+            // Not read through read_free_list_page, a damaged page isn't an error here
+            let free_list_page =
+                self.memory
+                    .get_page_ref::<FreeListPageRef>(page)
+                    .and_then(|free_list_page| {
+                        free_list_page.verify_checksum(page).map(|_| free_list_page)
+                    });
+            match free_list_page {
+                Ok(free_list_page) => page = free_list_page.get_free_list_page_next(),
+                Err(e) => {
+                    warn!(self.logger, "Free list chain lost at page {}: {}", page, e);
+                    break;
+                }
+            }
+        }
+    }
+}
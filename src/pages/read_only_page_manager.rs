@@ -0,0 +1,120 @@
+use crate::error::MemoryManagerError;
+use crate::memory_manager::MemoryManager;
+use crate::pages::config_page::{self, ConfigPageRef, MemoryLayout};
+use crate::pages::free_list_page::FreeListPageRef;
+use crate::pages::page_manager::find_meta_page;
+use slog::{crit, debug, info, warn};
+
+// Read-only counterpart of PageManager for inspection tools and reader processes.
+// It only needs shared access to the memory (which can be opened with
+// MemoryManager::open_read_only) and exposes the config history and the free lists;
+// there is no way to allocate, recycle or commit through it.
+pub struct ReadOnlyPageManager<'a> {
+    memory: &'a MemoryManager,
+    // The meta page holding the config page we read, see find_meta_page
+    config_page_index: u64,
+    pub last_used_page: u64,
+    pub recycled_pages_page: u64,
+    pub total_allocated_pages: u64,
+    pub version_number: u64,
+}
+
+impl<'a> ReadOnlyPageManager<'a> {
+    pub fn open(memory: &'a MemoryManager) -> Result<Self, MemoryManagerError> {
+        let log = memory.logger();
+
+        // Read the config page
+        info!(log, "Loading config page (read-only)...");
+        let config_page_index = find_meta_page(memory, log)?;
+        Self::read_config_page(memory, config_page_index, |config| {
+            config_page::check_superblock(config.data())
+        })?
+        .inspect_err(|e| {
+            crit!(log, "Invalid database file header: {}", e);
+        })?;
+        let (last_used_page, recycled_pages_page, total_allocated_pages, version_number) =
+            Self::read_config_page(memory, config_page_index, |config| {
+                (
+                    config.get_last_used_page(),
+                    config.get_recycled_pages_list(),
+                    config.get_total_allocated_pages(),
+                    config.get_version_number(),
+                )
+            })?;
+
+        if total_allocated_pages == 0 {
+            let err_msg =
+                "Database file is not initialized: total_allocated_pages == 0".to_string();
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::Corrupted(err_msg));
+        }
+        // The pages past total_allocated_pages are left by a crash after growing the file,
+        // nothing committed references them (see PageManager::open)
+        if total_allocated_pages < memory.num_pages() {
+            warn!(
+                log,
+                "Ignoring {} pages past total_allocated_pages {}",
+                memory.num_pages() - total_allocated_pages,
+                total_allocated_pages
+            );
+        } else if total_allocated_pages > memory.num_pages() {
+            let err_msg = format!(
+                "Database size mismatch: total_allocated_pages: {}, pages in file: {}",
+                total_allocated_pages,
+                memory.num_pages()
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::Corrupted(err_msg));
+        }
+
+        Ok(ReadOnlyPageManager {
+            memory,
+            config_page_index,
+            last_used_page,
+            recycled_pages_page,
+            total_allocated_pages,
+            version_number,
+        })
+    }
+
+    // Config header stored at the given version (0 is the current one)
+    pub fn get_memory_layout_at(&self, version: u64) -> Result<MemoryLayout, MemoryManagerError> {
+        Self::read_config_page(self.memory, self.config_page_index, |config| {
+            MemoryLayout::from_bytes_at(config, version)
+        })?
+    }
+
+    pub fn get_free_list_page_at(&self, version: u64) -> Result<Vec<u64>, MemoryManagerError> {
+        let recycled_pages_list =
+            Self::read_config_page(self.memory, self.config_page_index, |config| {
+                config.get_recycled_pages_list_at(version)
+            })??;
+        debug!(
+            self.memory.logger(),
+            "Recycled pages list at version {}: {:?}", version, recycled_pages_list
+        );
+        self.read_free_list_page(recycled_pages_list)
+    }
+
+    // Free pages stored in the current free list page
+    pub fn get_recycled_pages(&self) -> Result<Vec<u64>, MemoryManagerError> {
+        self.read_free_list_page(self.recycled_pages_page)
+    }
+
+    fn read_config_page<R>(
+        memory: &MemoryManager,
+        index: u64,
+        read: impl FnOnce(ConfigPageRef) -> R,
+    ) -> Result<R, MemoryManagerError> {
+        let config_page = memory.get_page_ref::<ConfigPageRef>(index)?;
+        Ok(read(ConfigPageRef::from(&config_page)))
+    }
+
+    fn read_free_list_page(&self, index: u64) -> Result<Vec<u64>, MemoryManagerError> {
+        let free_list_page = self.memory.get_page_ref::<FreeListPageRef>(index)?;
+        free_list_page.verify_checksum(index).inspect_err(|e| {
+            crit!(self.memory.logger(), "{}", e);
+        })?;
+        free_list_page.get_recycled_pages_list()
+    }
+}
@@ -0,0 +1,234 @@
+use super::memory_store::MemoryStore;
+use super::{check_page_range, check_page_size, PageStore};
+use crate::error::MemoryManagerError;
+use crate::memory_manager::lock;
+use slog::Logger;
+use std::io;
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+// A failure injected in a page write, write counts the writes since the fault was armed
+// (starting at 0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // The process dies right before the write
+    Crash { write: usize },
+    // Only the first bytes of the page reach the disk, then the process dies
+    TornWrite { write: usize, bytes: usize },
+    // The write is lost but the following ones of the same flush reach the disk, and the
+    // process dies before the flush returns: nothing orders the writes until the barrier.
+    DroppedWrite { write: usize },
+}
+
+impl Fault {
+    // The write the fault goes off at
+    pub fn write(&self) -> usize {
+        match *self {
+            Fault::Crash { write }
+            | Fault::TornWrite { write, .. }
+            | Fault::DroppedWrite { write } => write,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DiskState {
+    image: Vec<u8>,
+    page_size: u64,
+    // Every page written, in order
+    writes: Vec<u64>,
+    fault: Option<Fault>,
+    // Number of writes when the fault was armed
+    fault_base: usize,
+    crashed: bool,
+}
+
+// The disk behind a FaultStore, what survives a crash. Clones share the same disk, so a test
+// can keep one to look at the writes and to open a new store once the process "died".
+#[derive(Debug, Clone)]
+pub struct FaultDisk(Arc<Mutex<DiskState>>);
+
+impl FaultDisk {
+    // A blank disk of num_pages pages
+    pub fn new(num_pages: u64, page_size: u64) -> Result<Self, MemoryManagerError> {
+        check_page_size(page_size)?;
+        Ok(FaultDisk(Arc::new(Mutex::new(DiskState {
+            image: vec![0; (num_pages * page_size) as usize],
+            page_size,
+            writes: vec![],
+            fault: None,
+            fault_base: 0,
+            crashed: false,
+        }))))
+    }
+
+    // Arms (or disarms, with None) the fault, counting the writes from now on
+    pub fn set_fault(&self, fault: Option<Fault>) {
+        let mut disk = lock(&self.0);
+        disk.fault = fault;
+        disk.fault_base = disk.writes.len();
+    }
+
+    // Whether the fault went off. Once crashed every write fails until the disk is opened again,
+    // the fault is disarmed.
+    pub fn crashed(&self) -> bool {
+        lock(&self.0).crashed
+    }
+
+    // Pages written so far, in order
+    pub fn writes(&self) -> Vec<u64> {
+        lock(&self.0).writes.clone()
+    }
+
+    pub fn num_writes(&self) -> usize {
+        lock(&self.0).writes.len()
+    }
+
+    pub fn num_pages(&self) -> u64 {
+        let disk = lock(&self.0);
+        disk.image.len() as u64 / disk.page_size
+    }
+
+    // Contents of the disk
+    pub fn image(&self) -> Vec<u8> {
+        lock(&self.0).image.clone()
+    }
+}
+
+// Store for crash recovery tests. The pages live in memory like in a MemoryStore and only reach
+// the FaultDisk when they are flushed, one write per page that changed. A Fault makes one of
+// those writes fail like a power cut would: the disk keeps what a real disk could hold after
+// the crash and a new store opened on it sees the database as it would be after a restart.
+// Unflushed changes never reach the disk. Resizing is durable right away, like set_file_size.
+#[derive(Debug)]
+pub struct FaultStore {
+    memory: MemoryStore,
+    disk: FaultDisk,
+}
+
+impl FaultStore {
+    // Loads the pages stored on the disk, restarting it after a crash
+    pub fn open(disk: &FaultDisk) -> Result<Self, MemoryManagerError> {
+        let mut state = lock(&disk.0);
+        state.crashed = false;
+
+        let num_pages = state.image.len() as u64 / state.page_size;
+        let memory = MemoryStore::new(num_pages, state.page_size)?;
+        for index in 0..num_pages {
+            let page = memory.get_page_ptr(index)?;
+            let start = (index * state.page_size) as usize;
+            // The page is page_size bytes long and doesn't overlap the image
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    state.image[start..].as_ptr(),
+                    page,
+                    state.page_size as usize,
+                )
+            };
+        }
+        drop(state);
+
+        Ok(FaultStore {
+            memory,
+            disk: disk.clone(),
+        })
+    }
+
+    fn write_back(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        let mut disk = lock(&self.disk.0);
+        if disk.crashed {
+            return Err(crash_error());
+        }
+        let page_size = disk.page_size as usize;
+        let mut dropped = false;
+        for index in first_page..first_page + num_pages {
+            let ptr = self.memory.get_page_ptr(index)?;
+            // The store holds page_size bytes at ptr
+            let page = unsafe { slice::from_raw_parts(ptr, page_size) };
+            let start = index as usize * page_size;
+            // Clean pages are never written
+            if disk.image[start..start + page_size] == *page {
+                continue;
+            }
+
+            let write = disk.writes.len() - disk.fault_base;
+            // A fault only goes off once
+            let fault = disk.fault.filter(|fault| fault.write() == write);
+            if fault.is_some() {
+                disk.fault = None;
+            }
+            match fault {
+                Some(Fault::Crash { .. }) => {
+                    disk.crashed = true;
+                    return Err(crash_error());
+                }
+                Some(Fault::TornWrite { bytes, .. }) => {
+                    let bytes = bytes.min(page_size);
+                    disk.image[start..start + bytes].copy_from_slice(&page[..bytes]);
+                    disk.writes.push(index);
+                    disk.crashed = true;
+                    return Err(crash_error());
+                }
+                Some(Fault::DroppedWrite { .. }) => {
+                    disk.writes.push(index);
+                    dropped = true;
+                }
+                None => {
+                    disk.image[start..start + page_size].copy_from_slice(page);
+                    disk.writes.push(index);
+                }
+            }
+        }
+        if dropped {
+            disk.crashed = true;
+            return Err(crash_error());
+        }
+        Ok(())
+    }
+}
+
+fn crash_error() -> MemoryManagerError {
+    MemoryManagerError::Io(io::Error::other("Simulated crash"))
+}
+
+impl PageStore for FaultStore {
+    fn num_pages(&self) -> u64 {
+        self.memory.num_pages()
+    }
+
+    fn page_size(&self) -> u64 {
+        self.memory.page_size()
+    }
+
+    fn get_page_ptr(&self, index: u64) -> Result<*mut u8, MemoryManagerError> {
+        self.memory.get_page_ptr(index)
+    }
+
+    fn flush(&self) -> Result<(), MemoryManagerError> {
+        self.write_back(0, self.num_pages())
+    }
+
+    fn flush_range(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages())?;
+        self.write_back(first_page, num_pages)
+    }
+
+    fn resize(&mut self, num_pages: u64) -> Result<(), MemoryManagerError> {
+        let mut disk = lock(&self.disk.0);
+        if disk.crashed {
+            return Err(crash_error());
+        }
+        self.memory.resize(num_pages)?;
+        let len = (num_pages * disk.page_size) as usize;
+        disk.image.resize(len, 0);
+        Ok(())
+    }
+
+    fn logger(&self) -> &Logger {
+        self.memory.logger()
+    }
+
+    fn set_logger(&mut self, parent: &Logger) {
+        self.memory.set_logger(parent);
+    }
+}
@@ -0,0 +1,192 @@
+use super::{
+    check_page_index, check_page_range, create_file, open_file, set_file_size, Advice, PageStore,
+};
+use crate::error::MemoryManagerError;
+use crate::logger;
+use crate::memory_manager::lock;
+use slog::Logger;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+
+// Reads and writes pages with pread/pwrite instead of mapping the file, for filesystems
+// where mmap misbehaves.
+// Pages are read into a cache the first time they are requested and written back on flush.
+// MemoryManager releases the cached pages that aren't borrowed after every flush, so the
+// cache only holds the pages in use and the ones changed since the last flush. It's behind a
+// mutex, like every store this one can be shared between threads.
+#[derive(Debug)]
+pub struct FileStore {
+    filename: String,
+    file: File,
+    num_pages: u64,
+    page_size: u64,
+    pages: Mutex<HashMap<u64, Box<[u8]>>>,
+    logger: Logger,
+}
+
+impl FileStore {
+    // The store logs through a child of parent tagged with the file name
+    pub fn create(
+        filename: &str,
+        num_pages: u64,
+        page_size: u64,
+        parent: &Logger,
+    ) -> Result<Self, MemoryManagerError> {
+        let log = logger::file_logger(parent, filename);
+        let file = create_file(filename, num_pages, page_size, 0, true, false, &log)?;
+        Ok(Self::from_file(filename, file, num_pages, page_size, log))
+    }
+
+    pub fn open(filename: &str, parent: &Logger) -> Result<Self, MemoryManagerError> {
+        let log = logger::file_logger(parent, filename);
+        let (file, num_pages, page_size) = open_file(filename, true, &log)?;
+        Ok(Self::from_file(filename, file, num_pages, page_size, log))
+    }
+
+    // Uses a file opened (and locked) by create_file or open_file, logger is already tagged
+    // with filename
+    pub(crate) fn from_file(
+        filename: &str,
+        file: File,
+        num_pages: u64,
+        page_size: u64,
+        logger: Logger,
+    ) -> Self {
+        FileStore {
+            filename: filename.to_string(),
+            file,
+            num_pages,
+            page_size,
+            pages: Mutex::new(HashMap::new()),
+            logger,
+        }
+    }
+}
+
+impl FileStore {
+    fn write_page(&self, index: u64, page: &[u8]) -> Result<(), MemoryManagerError> {
+        self.file
+            .write_all_at(page, index * self.page_size)
+            .map_err(|e| {
+                let err_msg = format!("Flush has failed: {}", e);
+                MemoryManagerError::Io(io::Error::other(err_msg))
+            })
+    }
+}
+
+impl PageStore for FileStore {
+    fn num_pages(&self) -> u64 {
+        self.num_pages
+    }
+
+    fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    fn get_page_ptr(&self, index: u64) -> Result<*mut u8, MemoryManagerError> {
+        check_page_index(index, self.num_pages)?;
+        let mut pages = lock(&self.pages);
+        if let Some(page) = pages.get_mut(&index) {
+            return Ok(page.as_mut_ptr());
+        }
+        let mut page = vec![0u8; self.page_size as usize].into_boxed_slice();
+        self.file.read_exact_at(&mut page, index * self.page_size)?;
+        // The boxed page doesn't move when the map grows, so the pointer stays valid
+        let ptr = page.as_mut_ptr();
+        pages.insert(index, page);
+        Ok(ptr)
+    }
+
+    fn flush(&self) -> Result<(), MemoryManagerError> {
+        for (&index, page) in lock(&self.pages).iter() {
+            self.write_page(index, page)?;
+        }
+        Ok(self.file.sync_data()?)
+    }
+
+    fn flush_range(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        self.flush_range_async(first_page, num_pages)?;
+        Ok(self.file.sync_data()?)
+    }
+
+    // The pages are handed to the kernel but we don't wait for them to reach the disk
+    fn flush_range_async(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        let pages = lock(&self.pages);
+        for index in first_page..first_page + num_pages {
+            // Pages that were never requested can't have changed
+            if let Some(page) = pages.get(&index) {
+                self.write_page(index, page)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Forwarded to the page cache of the file with posix_fadvise
+    fn advise_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        advice: Advice,
+    ) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        let advice = match advice {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+        };
+        let ret = unsafe {
+            libc::posix_fadvise(
+                self.file.as_raw_fd(),
+                (first_page * self.page_size) as libc::off_t,
+                (num_pages * self.page_size) as libc::off_t,
+                advice,
+            )
+        };
+        if ret != 0 {
+            let err_msg = format!(
+                "posix_fadvise has failed: {}",
+                io::Error::from_raw_os_error(ret)
+            );
+            return Err(MemoryManagerError::Io(io::Error::other(err_msg)));
+        }
+        Ok(())
+    }
+
+    // The released pages have just been written, they are read again on the next request
+    fn release_pages(&self, keep: &dyn Fn(u64) -> bool) {
+        lock(&self.pages).retain(|&index, _| keep(index));
+    }
+
+    fn num_cached_pages(&self) -> u64 {
+        lock(&self.pages).len() as u64
+    }
+
+    fn resize(&mut self, num_pages: u64) -> Result<(), MemoryManagerError> {
+        set_file_size(&self.file, num_pages, self.page_size, &self.logger)?;
+        lock(&self.pages).retain(|&index, _| index < num_pages);
+        self.num_pages = num_pages;
+        Ok(())
+    }
+
+    fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    fn set_logger(&mut self, parent: &Logger) {
+        self.logger = logger::file_logger(parent, &self.filename);
+    }
+}
+
+// Unflushed pages of a memory mapped file still reach the disk, do the same here
+impl Drop for FileStore {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
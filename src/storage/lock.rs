@@ -0,0 +1,79 @@
+use crate::error::MemoryManagerError;
+use slog::{crit, info, Logger};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+// Advisory lock on the whole database file, released when the file is closed.
+// Writers hold it exclusively and read-only openers share it, so a second writer (or a
+// writer while there are readers) fails to open instead of corrupting the config page.
+//
+// The lock itself is a flock, which belongs to the open file description and therefore
+// also conflicts with a second open of the same file in this process. flock can't tell who
+// holds it, so we take a POSIX record lock of the same type next to it that F_GETLK can
+// report.
+pub(crate) fn lock_file(
+    file: &File,
+    filename: &str,
+    exclusive: bool,
+    log: &Logger,
+) -> Result<(), MemoryManagerError> {
+    let fd = file.as_raw_fd();
+
+    let operation = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    // Safe: fd is a valid descriptor owned by file
+    if unsafe { libc::flock(fd, operation | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::WouldBlock {
+            let err_msg = format!("Failed to lock file: {} - {}", filename, e);
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::Io(io::Error::new(e.kind(), err_msg)));
+        }
+        let err = MemoryManagerError::Locked {
+            pid: lock_holder(fd, exclusive),
+        };
+        crit!(log, "{}: {}", filename, &err);
+        return Err(err);
+    }
+
+    // Best effort, it's only used to report the pid
+    let mut lock = record_lock(exclusive);
+    unsafe { libc::fcntl(fd, libc::F_SETLK, &mut lock) };
+
+    info!(
+        log,
+        "File {} locked ({})",
+        filename,
+        if exclusive { "exclusive" } else { "shared" }
+    );
+    Ok(())
+}
+
+// Pid of a process holding a lock that conflicts with ours
+fn lock_holder(fd: i32, exclusive: bool) -> u32 {
+    let mut lock = record_lock(exclusive);
+    if unsafe { libc::fcntl(fd, libc::F_GETLK, &mut lock) } == 0
+        && lock.l_type != libc::F_UNLCK as libc::c_short
+    {
+        return lock.l_pid as u32;
+    }
+    // Record locks never conflict inside a process, so the flock is held through
+    // another descriptor of this same process
+    std::process::id()
+}
+
+fn record_lock(exclusive: bool) -> libc::flock {
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = if exclusive {
+        libc::F_WRLCK
+    } else {
+        libc::F_RDLCK
+    } as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    // l_start and l_len are 0, the lock covers the whole file
+    lock
+}
@@ -0,0 +1,103 @@
+use super::{check_page_index, check_page_range, check_page_size, mlock_range, PageStore};
+use crate::error::MemoryManagerError;
+use crate::logger;
+use memmap2::{MmapOptions, MmapRaw};
+use slog::Logger;
+use std::io;
+
+// Anonymous mapping that is never written to disk, for tests and ephemeral caches.
+// Flushing is a no-op and resizing copies the pages into a new mapping.
+#[derive(Debug)]
+pub struct MemoryStore {
+    mmap: MmapRaw,
+    num_pages: u64,
+    page_size: u64,
+    logger: Logger,
+}
+
+impl MemoryStore {
+    pub fn new(num_pages: u64, page_size: u64) -> Result<Self, MemoryManagerError> {
+        check_page_size(page_size)?;
+        Ok(MemoryStore {
+            mmap: Self::map_anon(num_pages, page_size)?,
+            num_pages,
+            page_size,
+            logger: logger::get_logger().clone(),
+        })
+    }
+
+    fn map_anon(num_pages: u64, page_size: u64) -> Result<MmapRaw, MemoryManagerError> {
+        let mmap = MmapOptions::new()
+            .len((num_pages * page_size) as usize)
+            .map_anon()
+            .map_err(|e| {
+                let err_msg = format!("Failed to create anonymous memory map: {}", e);
+                MemoryManagerError::Io(io::Error::other(err_msg))
+            })?;
+        Ok(MmapRaw::from(mmap))
+    }
+}
+
+impl PageStore for MemoryStore {
+    fn num_pages(&self) -> u64 {
+        self.num_pages
+    }
+
+    fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    fn get_page_ptr(&self, index: u64) -> Result<*mut u8, MemoryManagerError> {
+        check_page_index(index, self.num_pages)?;
+        // index < num_pages so the offset is inside the mapping
+        unsafe {
+            Ok(self
+                .mmap
+                .as_mut_ptr()
+                .add((index * self.page_size) as usize))
+        }
+    }
+
+    fn flush(&self) -> Result<(), MemoryManagerError> {
+        Ok(())
+    }
+
+    fn flush_range(&self, _first_page: u64, _num_pages: u64) -> Result<(), MemoryManagerError> {
+        Ok(())
+    }
+
+    fn lock_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        lock: bool,
+    ) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        // The range is inside the mapping
+        let ptr = unsafe {
+            self.mmap
+                .as_ptr()
+                .add((first_page * self.page_size) as usize)
+        };
+        mlock_range(ptr, (num_pages * self.page_size) as usize, lock)
+    }
+
+    fn resize(&mut self, num_pages: u64) -> Result<(), MemoryManagerError> {
+        let mmap = Self::map_anon(num_pages, self.page_size)?;
+        let len = (self.num_pages.min(num_pages) * self.page_size) as usize;
+        // Both mappings are at least len bytes long and they don't overlap
+        unsafe { std::ptr::copy_nonoverlapping(self.mmap.as_ptr(), mmap.as_mut_ptr(), len) };
+        self.mmap = mmap;
+        self.num_pages = num_pages;
+        Ok(())
+    }
+
+    fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    // There's no file to tag the records with, parent is used as it is
+    fn set_logger(&mut self, parent: &Logger) {
+        self.logger = parent.clone();
+    }
+}
@@ -0,0 +1,255 @@
+use super::{
+    check_page_index, check_page_range, create_file, mlock_range, open_file, set_file_size, Advice,
+    MapFlags, PageStore,
+};
+use crate::error::MemoryManagerError;
+use crate::logger;
+use memmap2::{MmapOptions, MmapRaw, UncheckedAdvice};
+use slog::{crit, info, warn, Logger};
+use std::fs::File;
+use std::io;
+
+// Maps the whole file into memory, pages are read and written in place
+#[derive(Debug)]
+pub struct MmapStore {
+    filename: String,
+    file: File,
+    mmap: MmapRaw,
+    num_pages: u64,
+    page_size: u64,
+    read_only: bool,
+    flags: MapFlags,
+    logger: Logger,
+}
+
+impl MmapStore {
+    // The store logs through a child of parent tagged with the file name
+    pub fn create(
+        filename: &str,
+        num_pages: u64,
+        page_size: u64,
+        parent: &Logger,
+    ) -> Result<Self, MemoryManagerError> {
+        let log = logger::file_logger(parent, filename);
+        let file = create_file(filename, num_pages, page_size, 0, true, false, &log)?;
+        Self::map_file(
+            filename,
+            file,
+            num_pages,
+            page_size,
+            MapFlags::default(),
+            log,
+        )
+    }
+
+    pub fn open(filename: &str, parent: &Logger) -> Result<Self, MemoryManagerError> {
+        let log = logger::file_logger(parent, filename);
+        let (file, num_pages, page_size) = open_file(filename, true, &log)?;
+        Self::map_file(
+            filename,
+            file,
+            num_pages,
+            page_size,
+            MapFlags::default(),
+            log,
+        )
+    }
+
+    // Opens the file read-only and maps it with PROT_READ
+    pub fn open_read_only(filename: &str, parent: &Logger) -> Result<Self, MemoryManagerError> {
+        let log = logger::file_logger(parent, filename);
+        let (file, num_pages, page_size) = open_file(filename, false, &log)?;
+        let mmap = MmapOptions::new().map_raw_read_only(&file).map_err(|e| {
+            let err_msg = format!("Failed to create read-only memory map: {}", e);
+            crit!(log, "{}", &err_msg);
+            MemoryManagerError::Io(io::Error::other(err_msg))
+        })?;
+
+        info!(
+            log,
+            "Correctly mapped {} pages into memory (read-only)", num_pages
+        );
+
+        Ok(MmapStore {
+            filename: filename.to_string(),
+            file,
+            mmap,
+            num_pages,
+            page_size,
+            read_only: true,
+            flags: MapFlags::default(),
+            logger: log,
+        })
+    }
+
+    // Maps a file opened (and locked) by create_file or open_file, logger is already tagged
+    // with filename
+    pub(crate) fn map_file(
+        filename: &str,
+        file: File,
+        num_pages: u64,
+        page_size: u64,
+        flags: MapFlags,
+        logger: Logger,
+    ) -> Result<Self, MemoryManagerError> {
+        let mmap = Self::map_raw(&file, flags, &logger)?;
+
+        info!(logger, "Correctly mapped {} pages into memory", num_pages);
+
+        Ok(MmapStore {
+            filename: filename.to_string(),
+            file,
+            mmap,
+            num_pages,
+            page_size,
+            read_only: false,
+            flags,
+            logger,
+        })
+    }
+
+    fn map_raw(file: &File, flags: MapFlags, log: &Logger) -> Result<MmapRaw, MemoryManagerError> {
+        // Open a memory map for the file
+        let mut options = MmapOptions::new();
+        if flags.populate {
+            options.populate();
+        }
+        let mmap = options.map_raw(file).map_err(|e| {
+            let err_msg = format!("Failed to create memory map: {}", e);
+            crit!(log, "{}", &err_msg);
+            MemoryManagerError::Io(io::Error::other(err_msg))
+        })?;
+        // Only a hint, not every filesystem supports huge pages
+        if flags.huge_pages {
+            if let Err(e) = mmap.advise(memmap2::Advice::HugePage) {
+                warn!(log, "Huge pages are not available: {}", e);
+            }
+        }
+        Ok(mmap)
+    }
+}
+
+impl PageStore for MmapStore {
+    fn num_pages(&self) -> u64 {
+        self.num_pages
+    }
+
+    fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    fn get_page_ptr(&self, index: u64) -> Result<*mut u8, MemoryManagerError> {
+        check_page_index(index, self.num_pages)?;
+        // index < num_pages so the offset is inside the mapping
+        unsafe {
+            Ok(self
+                .mmap
+                .as_mut_ptr()
+                .add((index * self.page_size) as usize))
+        }
+    }
+
+    fn flush(&self) -> Result<(), MemoryManagerError> {
+        self.mmap.flush().map_err(|e| {
+            let err_msg = format!("Flush has failed: {}", e);
+            MemoryManagerError::Io(io::Error::other(err_msg))
+        })
+    }
+
+    fn flush_range(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        self.mmap
+            .flush_range(
+                (first_page * self.page_size) as usize,
+                (num_pages * self.page_size) as usize,
+            )
+            .map_err(|e| {
+                let err_msg = format!("Flush has failed: {}", e);
+                MemoryManagerError::Io(io::Error::other(err_msg))
+            })
+    }
+
+    fn flush_range_async(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        self.mmap
+            .flush_async_range(
+                (first_page * self.page_size) as usize,
+                (num_pages * self.page_size) as usize,
+            )
+            .map_err(|e| {
+                let err_msg = format!("Flush has failed: {}", e);
+                MemoryManagerError::Io(io::Error::other(err_msg))
+            })
+    }
+
+    fn advise_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        advice: Advice,
+    ) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        let offset = (first_page * self.page_size) as usize;
+        let len = (num_pages * self.page_size) as usize;
+        let result = match advice {
+            Advice::Normal => self.mmap.advise_range(memmap2::Advice::Normal, offset, len),
+            Advice::Sequential => self
+                .mmap
+                .advise_range(memmap2::Advice::Sequential, offset, len),
+            Advice::Random => self.mmap.advise_range(memmap2::Advice::Random, offset, len),
+            Advice::WillNeed => self
+                .mmap
+                .advise_range(memmap2::Advice::WillNeed, offset, len),
+            // The mapping is shared with the file, so the pages are read back from the page
+            // cache (or the file) instead of being zeroed
+            Advice::DontNeed => unsafe {
+                self.mmap
+                    .unchecked_advise_range(UncheckedAdvice::DontNeed, offset, len)
+            },
+        };
+        result.map_err(|e| {
+            let err_msg = format!("madvise has failed: {}", e);
+            MemoryManagerError::Io(io::Error::other(err_msg))
+        })
+    }
+
+    fn lock_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        lock: bool,
+    ) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        // The range is inside the mapping
+        let ptr = unsafe {
+            self.mmap
+                .as_ptr()
+                .add((first_page * self.page_size) as usize)
+        };
+        mlock_range(ptr, (num_pages * self.page_size) as usize, lock)
+    }
+
+    fn resize(&mut self, num_pages: u64) -> Result<(), MemoryManagerError> {
+        if self.read_only {
+            return Err(MemoryManagerError::ReadOnly(
+                "Cannot resize a read-only store".to_string(),
+            ));
+        }
+        set_file_size(&self.file, num_pages, self.page_size, &self.logger)?;
+        self.mmap = Self::map_raw(&self.file, self.flags, &self.logger)?;
+        self.num_pages = num_pages;
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    fn set_logger(&mut self, parent: &Logger) {
+        self.logger = logger::file_logger(parent, &self.filename);
+    }
+}
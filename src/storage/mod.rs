@@ -0,0 +1,363 @@
+use crate::error::MemoryManagerError;
+use crate::logger;
+use crate::pages::config_page;
+use slog::{crit, info, Logger};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod fault_store;
+pub mod file_store;
+mod lock;
+pub mod memory_store;
+pub mod mmap_store;
+pub mod segmented_store;
+
+pub const DEFAULT_PAGE_SIZE: u64 = 0x1000; // 4KB
+pub const MIN_PAGE_SIZE: u64 = 0x1000; // 4KB
+pub const MAX_PAGE_SIZE: u64 = 0x200000; // 2MB
+
+// How a file backed store maps the file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MapFlags {
+    // Pre-fault the whole mapping (MAP_POPULATE) instead of faulting pages on first access
+    pub populate: bool,
+    // Ask for transparent huge pages (MADV_HUGEPAGE)
+    pub huge_pages: bool,
+}
+
+// Expected access pattern for a range of pages, see MemoryManager::advise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    // Default readahead
+    Normal,
+    // The pages are read in order, read ahead aggressively
+    Sequential,
+    // The pages are read in no particular order, don't read ahead
+    Random,
+    // The pages will be needed soon, start reading them
+    WillNeed,
+    // The pages won't be needed soon, the kernel can drop them from memory.
+    // Their contents are kept, they are read again on the next access.
+    DontNeed,
+}
+
+// Storage backend behind MemoryManager. A store is a resizable array of pages of page_size bytes.
+// Stores are Send and Sync so a MemoryManager can be moved to and shared between threads.
+pub trait PageStore: fmt::Debug + Send + Sync {
+    // Number of pages in the store
+    fn num_pages(&self) -> u64;
+
+    // Size of every page in bytes
+    fn page_size(&self) -> u64;
+
+    // Returns a pointer to the first byte of the page, page_size bytes can be read and written
+    // through it. The pointer stays valid until the next resize or until the store is dropped.
+    fn get_page_ptr(&self, index: u64) -> Result<*mut u8, MemoryManagerError>;
+
+    // Makes every change written through the page pointers durable
+    fn flush(&self) -> Result<(), MemoryManagerError>;
+
+    // Makes the changes to num_pages pages starting at first_page durable
+    fn flush_range(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        let _ = (first_page, num_pages);
+        self.flush()
+    }
+
+    // Starts writing back num_pages pages starting at first_page without waiting for the disk
+    fn flush_range_async(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        self.flush_range(first_page, num_pages)
+    }
+
+    // Hints how num_pages pages starting at first_page are going to be accessed.
+    // Only a hint, stores that can't make use of it ignore it.
+    fn advise_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        advice: Advice,
+    ) -> Result<(), MemoryManagerError> {
+        let _ = (first_page, num_pages, advice);
+        Ok(())
+    }
+
+    // Keeps num_pages pages starting at first_page resident in memory (lock == true) or lets
+    // them be paged out again (lock == false). Stores that don't map the pages ignore it.
+    // Locks don't survive a resize.
+    fn lock_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        lock: bool,
+    ) -> Result<(), MemoryManagerError> {
+        let _ = (first_page, num_pages, lock);
+        Ok(())
+    }
+
+    // Drops the copies kept in memory of the pages for which keep returns false, their pointers
+    // are invalid afterwards. Only called right after a flush, when no page is dirty.
+    // Stores that don't copy the pages ignore it.
+    fn release_pages(&self, keep: &dyn Fn(u64) -> bool) {
+        let _ = keep;
+    }
+
+    // Number of pages the store keeps a copy of in memory
+    fn num_cached_pages(&self) -> u64 {
+        0
+    }
+
+    // Grows or shrinks the store to num_pages. Every page pointer is invalid afterwards.
+    fn resize(&mut self, num_pages: u64) -> Result<(), MemoryManagerError>;
+
+    // Read-only stores hand out pointers that must never be written through
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    // Logger the store reports through, file backed stores tag it with their file name
+    fn logger(&self) -> &Logger {
+        logger::get_logger()
+    }
+
+    // Makes the store report through a child of parent, tagged like the one it was created
+    // with. Stores that don't keep a logger ignore it.
+    fn set_logger(&mut self, parent: &Logger) {
+        let _ = parent;
+    }
+}
+
+pub(crate) fn check_page_index(index: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+    if index >= num_pages {
+        return Err(MemoryManagerError::OutOfBounds {
+            first_page: index,
+            num_pages: 1,
+        });
+    }
+    Ok(())
+}
+
+// Locks (or unlocks) len bytes starting at ptr in memory so they are never paged out
+pub(crate) fn mlock_range(
+    ptr: *const u8,
+    len: usize,
+    lock: bool,
+) -> Result<(), MemoryManagerError> {
+    let ret = unsafe {
+        if lock {
+            libc::mlock(ptr as *const libc::c_void, len)
+        } else {
+            libc::munlock(ptr as *const libc::c_void, len)
+        }
+    };
+    if ret != 0 {
+        let e = io::Error::last_os_error();
+        let err_msg = format!(
+            "{} has failed: {}",
+            if lock { "mlock" } else { "munlock" },
+            e
+        );
+        return Err(MemoryManagerError::Io(io::Error::new(e.kind(), err_msg)));
+    }
+    Ok(())
+}
+
+// Page sizes must be a power of two between MIN_PAGE_SIZE and MAX_PAGE_SIZE
+pub fn check_page_size(page_size: u64) -> Result<(), MemoryManagerError> {
+    if !page_size.is_power_of_two() || !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(MemoryManagerError::InvalidInput(format!(
+            "Invalid page size {}: it must be a power of two between {} and {}",
+            page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_page_range(
+    first_page: u64,
+    num_pages: u64,
+    total_pages: u64,
+) -> Result<(), MemoryManagerError> {
+    if first_page
+        .checked_add(num_pages)
+        .is_none_or(|end| end > total_pages)
+    {
+        return Err(MemoryManagerError::OutOfBounds {
+            first_page,
+            num_pages,
+        });
+    }
+    Ok(())
+}
+
+// Creates (or reuses) the file, locks it exclusively and sets its size to num_pages.
+// Without create the file must already exist, with truncate its contents are discarded.
+// A file that is reused must be one of our databases with the same page size (and the same
+// segment size, unless pages_per_segment is 0), anything else is refused before touching it.
+// The segment size is only written to a new file, see check_pages_per_segment.
+pub(crate) fn create_file(
+    filename: &str,
+    num_pages: u64,
+    page_size: u64,
+    pages_per_segment: u64,
+    create: bool,
+    truncate: bool,
+    log: &Logger,
+) -> Result<File, MemoryManagerError> {
+    check_page_size(page_size)?;
+    // Open the memory-mapped file
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(create)
+        .truncate(false)
+        .open(filename)
+        .map_err(|e| {
+            let err_msg = format!("Failed to open file: {} - {}", filename, e);
+            crit!(log, "{}", &err_msg);
+            io::Error::new(e.kind(), err_msg)
+        })?;
+    info!(log, "File {} opened", filename);
+    // Lock before resizing, we must not truncate a file another writer is using
+    lock::lock_file(&file, filename, true, log)?;
+    if truncate {
+        info!(log, "Truncating file {}", filename);
+        file.set_len(0)?;
+    }
+    if file.metadata()?.len() == 0 {
+        // Write the superblock right away so the file can be reopened before the config page
+        // is initialized
+        let mut header = [0u8; config_page::FILE_HEADER_BYTES];
+        config_page::write_superblock(&mut header, page_size)?;
+        config_page::set_pages_per_segment(&mut header, pages_per_segment)?;
+        file.write_all_at(&header, 0)?;
+    } else {
+        let file_page_size = read_superblock(&file, filename, log)?;
+        if file_page_size != page_size {
+            let err_msg = format!(
+                "Page size mismatch: file page size: {}, requested page size: {}",
+                file_page_size, page_size
+            );
+            crit!(log, "{}", &err_msg);
+            return Err(MemoryManagerError::InvalidInput(err_msg));
+        }
+        check_pages_per_segment(&file, pages_per_segment, log)?;
+    }
+    set_file_size(&file, num_pages, page_size, log)?;
+    info!(
+        log,
+        "File size: {:?} MB",
+        (page_size * num_pages) as f64 / 1_048_576.0
+    );
+    Ok(file)
+}
+
+// Opens an existing file, locked exclusively when writable and shared otherwise.
+// The page size is read from the file header
+// and the number of pages is taken from the file size.
+pub(crate) fn open_file(
+    filename: &str,
+    writable: bool,
+    log: &Logger,
+) -> Result<(File, u64, u64), MemoryManagerError> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(writable)
+        .open(filename)
+        .map_err(|e| {
+            let err_msg = format!("Failed to open file: {} - {}", filename, e);
+            crit!(log, "{}", &err_msg);
+            io::Error::new(e.kind(), err_msg)
+        })?;
+    info!(log, "File {} opened", filename);
+    lock::lock_file(&file, filename, writable, log)?;
+
+    let page_size = read_superblock(&file, filename, log)?;
+    let file_size = file.metadata()?.len();
+    if file_size % page_size != 0 {
+        let err_msg = format!(
+            "Invalid database file size: {} is not a multiple of the page size {}",
+            file_size, page_size
+        );
+        crit!(log, "{}", &err_msg);
+        return Err(MemoryManagerError::Corrupted(err_msg));
+    }
+    info!(log, "File size: {:?} MB", file_size as f64 / 1_048_576.0);
+
+    Ok((file, file_size / page_size, page_size))
+}
+
+// Fails if the file header records a segment size other than pages_per_segment. 0 skips the
+// check. A file without one (a regular database) is refused too: the header is part of meta
+// page 0, writing the segment size into it would break the checksum of the committed root.
+pub(crate) fn check_pages_per_segment(
+    file: &File,
+    pages_per_segment: u64,
+    log: &Logger,
+) -> Result<(), MemoryManagerError> {
+    if pages_per_segment == 0 {
+        return Ok(());
+    }
+    let file_pages_per_segment = read_pages_per_segment(file, log)?;
+    if file_pages_per_segment != pages_per_segment {
+        let err_msg = format!(
+            "Segment size mismatch: file pages per segment: {}, requested pages per segment: {}",
+            file_pages_per_segment, pages_per_segment
+        );
+        crit!(log, "{}", &err_msg);
+        return Err(MemoryManagerError::InvalidInput(err_msg));
+    }
+    Ok(())
+}
+
+// Reads the segment size recorded in the file header, a regular database has none
+pub(crate) fn read_pages_per_segment(file: &File, log: &Logger) -> Result<u64, MemoryManagerError> {
+    let mut header = [0u8; config_page::FILE_HEADER_BYTES];
+    file.read_exact_at(&mut header, 0)?;
+    let pages_per_segment = config_page::pages_per_segment_from_header(&header);
+    if pages_per_segment == 0 {
+        let err_msg = "Not a segmented database: the file header records no segment size";
+        crit!(log, "{}", err_msg);
+        return Err(MemoryManagerError::InvalidInput(err_msg.to_string()));
+    }
+    Ok(pages_per_segment)
+}
+
+// Reads and validates the superblock of an existing file, returns its page size
+fn read_superblock(file: &File, filename: &str, log: &Logger) -> Result<u64, MemoryManagerError> {
+    let mut header = [0u8; config_page::FILE_HEADER_BYTES];
+    file.read_exact_at(&mut header, 0).map_err(|e| {
+        let err_msg = format!("Failed to read file header: {} - {}", filename, e);
+        crit!(log, "{}", &err_msg);
+        MemoryManagerError::Corrupted(err_msg)
+    })?;
+    config_page::check_superblock(&header).inspect_err(|e| {
+        crit!(log, "Invalid database file header: {}", e);
+    })?;
+    let page_size = config_page::page_size_from_header(&header);
+    check_page_size(page_size).map_err(|e| {
+        let err_msg = format!("Invalid database file header: {}", e);
+        crit!(log, "{}", &err_msg);
+        MemoryManagerError::Corrupted(err_msg)
+    })?;
+    Ok(page_size)
+}
+
+pub(crate) fn set_file_size(
+    file: &File,
+    num_pages: u64,
+    page_size: u64,
+    log: &Logger,
+) -> Result<(), MemoryManagerError> {
+    let file_size: u64 = page_size * num_pages;
+    // The new size is made durable right away, msync doesn't persist it and a root must never
+    // reference pages past the end of the file after a crash
+    file.set_len(file_size)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| {
+            let err_msg = format!("Failed to set file size: {} - {}", file_size, e);
+            crit!(log, "{}", &err_msg);
+            MemoryManagerError::Io(io::Error::new(e.kind(), err_msg))
+        })
+}
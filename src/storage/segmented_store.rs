@@ -0,0 +1,349 @@
+use super::mmap_store::MmapStore;
+use super::{
+    check_page_index, check_page_range, create_file, lock, open_file, read_pages_per_segment,
+    set_file_size, Advice, MapFlags, PageStore, DEFAULT_PAGE_SIZE,
+};
+use crate::error::MemoryManagerError;
+use crate::logger;
+use slog::{crit, info, Logger};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+// 1GB segments with the default page size
+pub const DEFAULT_PAGES_PER_SEGMENT: u64 = 0x4000_0000 / DEFAULT_PAGE_SIZE;
+
+// Spreads the pages over several memory mapped files (segments) of pages_per_segment pages.
+// Segment 0 is the file itself and holds the file header and the lock, segment N is stored
+// in "<filename>.N". Page i lives in segment i / pages_per_segment.
+// Every segment but the last one is full. Growing creates the segments that are missing,
+// shrinking deletes the ones that are no longer needed.
+// pages_per_segment is recorded in the file header of segment 0 when it's created. A regular
+// database has none and can't be opened as a segmented one. open reads it back, creating the
+// database again with another segment size fails.
+#[derive(Debug)]
+pub struct SegmentedStore {
+    filename: String,
+    segments: Vec<MmapStore>,
+    pages_per_segment: u64,
+    num_pages: u64,
+    page_size: u64,
+    logger: Logger,
+}
+
+impl SegmentedStore {
+    // Every segment logs through a child of parent tagged with the name of segment 0
+    pub fn create(
+        filename: &str,
+        num_pages: u64,
+        page_size: u64,
+        pages_per_segment: u64,
+        parent: &Logger,
+    ) -> Result<Self, MemoryManagerError> {
+        check_segment_size(num_pages, pages_per_segment)?;
+        let log = logger::file_logger(parent, filename);
+        let first_segment_pages = num_pages.min(pages_per_segment);
+        let file = create_file(
+            filename,
+            first_segment_pages,
+            page_size,
+            pages_per_segment,
+            true,
+            false,
+            &log,
+        )?;
+        let mut store = SegmentedStore {
+            filename: filename.to_string(),
+            segments: vec![MmapStore::map_file(
+                filename,
+                file,
+                first_segment_pages,
+                page_size,
+                MapFlags::default(),
+                log.clone(),
+            )?],
+            pages_per_segment,
+            num_pages: first_segment_pages,
+            page_size,
+            logger: log,
+        };
+        // Segments left behind by a previous, larger database
+        store.remove_segments_from(num_pages.div_ceil(pages_per_segment) as usize)?;
+        store.resize(num_pages)?;
+        Ok(store)
+    }
+
+    // Opens segment 0 and every segment after it, the page size and the segment size are read
+    // from the header of segment 0
+    pub fn open(filename: &str, parent: &Logger) -> Result<Self, MemoryManagerError> {
+        let log = logger::file_logger(parent, filename);
+        let (file, first_segment_pages, page_size) = open_file(filename, true, &log)?;
+        let pages_per_segment = read_pages_per_segment(&file, &log)?;
+        check_segment_size(first_segment_pages, pages_per_segment)?;
+        check_segment_pages(0, first_segment_pages, pages_per_segment, &log)?;
+        let mut store = SegmentedStore {
+            filename: filename.to_string(),
+            segments: vec![MmapStore::map_file(
+                filename,
+                file,
+                first_segment_pages,
+                page_size,
+                MapFlags::default(),
+                log.clone(),
+            )?],
+            pages_per_segment,
+            num_pages: first_segment_pages,
+            page_size,
+            logger: log.clone(),
+        };
+
+        while Path::new(&store.segment_filename(store.segments.len())).exists() {
+            let index = store.segments.len();
+            if store.num_pages != index as u64 * pages_per_segment {
+                let err_msg = format!(
+                    "Invalid segmented database: segment {} is not full but segment {} exists",
+                    index - 1,
+                    index
+                );
+                crit!(log, "{}", &err_msg);
+                return Err(MemoryManagerError::Corrupted(err_msg));
+            }
+            let file = store.open_segment(index, false)?;
+            let file_size = file.metadata()?.len();
+            let segment_pages = file_size / page_size;
+            if file_size % page_size != 0 || segment_pages == 0 {
+                let err_msg = format!(
+                    "Invalid segment size: {} is not a non-zero multiple of the page size {}",
+                    file_size, page_size
+                );
+                crit!(log, "{}", &err_msg);
+                return Err(MemoryManagerError::Corrupted(err_msg));
+            }
+            check_segment_pages(index, segment_pages, pages_per_segment, &log)?;
+            store.segments.push(MmapStore::map_file(
+                filename,
+                file,
+                segment_pages,
+                page_size,
+                MapFlags::default(),
+                log.clone(),
+            )?);
+            store.num_pages += segment_pages;
+        }
+
+        info!(
+            log,
+            "Opened {} pages in {} segments",
+            store.num_pages,
+            store.segments.len()
+        );
+        Ok(store)
+    }
+
+    pub fn pages_per_segment(&self) -> u64 {
+        self.pages_per_segment
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn segment_filename(&self, index: usize) -> String {
+        if index == 0 {
+            self.filename.clone()
+        } else {
+            format!("{}.{}", self.filename, index)
+        }
+    }
+
+    // Opens (or creates) the file of a segment other than the first one, it's locked
+    // together with segment 0
+    fn open_segment(&self, index: usize, create: bool) -> Result<File, MemoryManagerError> {
+        let filename = self.segment_filename(index);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(&filename)
+            .map_err(|e| {
+                let err_msg = format!("Failed to open segment: {} - {}", filename, e);
+                crit!(self.logger, "{}", &err_msg);
+                io::Error::new(e.kind(), err_msg)
+            })?;
+        lock::lock_file(&file, &filename, true, &self.logger)?;
+        Ok(file)
+    }
+
+    // Deletes the files of segment index and the ones after it
+    fn remove_segments_from(&mut self, index: usize) -> Result<(), MemoryManagerError> {
+        let index = index.max(1);
+        self.segments.truncate(index);
+        let mut next = index;
+        while Path::new(&self.segment_filename(next)).exists() {
+            fs::remove_file(self.segment_filename(next))?;
+            info!(
+                self.logger,
+                "Removed segment {}",
+                self.segment_filename(next)
+            );
+            next += 1;
+        }
+        Ok(())
+    }
+
+    // Splits num_pages pages starting at first_page into (segment, first_page, num_pages)
+    // ranges local to each segment
+    fn segment_ranges(&self, first_page: u64, num_pages: u64) -> Vec<(usize, u64, u64)> {
+        let mut ranges = vec![];
+        let mut page = first_page;
+        let end = first_page + num_pages;
+        while page < end {
+            let segment = page / self.pages_per_segment;
+            let offset = page % self.pages_per_segment;
+            let len = (self.pages_per_segment - offset).min(end - page);
+            ranges.push((segment as usize, offset, len));
+            page += len;
+        }
+        ranges
+    }
+}
+
+fn check_segment_size(num_pages: u64, pages_per_segment: u64) -> Result<(), MemoryManagerError> {
+    if num_pages == 0 || pages_per_segment == 0 {
+        return Err(MemoryManagerError::InvalidInput(
+            "The number of pages and the pages per segment must be greater than 0".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn check_segment_pages(
+    index: usize,
+    num_pages: u64,
+    pages_per_segment: u64,
+    log: &Logger,
+) -> Result<(), MemoryManagerError> {
+    if num_pages > pages_per_segment {
+        let err_msg = format!(
+            "Invalid segment size: segment {} has {} pages, more than {} pages per segment",
+            index, num_pages, pages_per_segment
+        );
+        crit!(log, "{}", &err_msg);
+        return Err(MemoryManagerError::Corrupted(err_msg));
+    }
+    Ok(())
+}
+
+impl PageStore for SegmentedStore {
+    fn num_pages(&self) -> u64 {
+        self.num_pages
+    }
+
+    fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    fn get_page_ptr(&self, index: u64) -> Result<*mut u8, MemoryManagerError> {
+        check_page_index(index, self.num_pages)?;
+        self.segments[(index / self.pages_per_segment) as usize]
+            .get_page_ptr(index % self.pages_per_segment)
+    }
+
+    fn flush(&self) -> Result<(), MemoryManagerError> {
+        for segment in &self.segments {
+            segment.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush_range(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        for (segment, first_page, num_pages) in self.segment_ranges(first_page, num_pages) {
+            self.segments[segment].flush_range(first_page, num_pages)?;
+        }
+        Ok(())
+    }
+
+    fn flush_range_async(&self, first_page: u64, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        for (segment, first_page, num_pages) in self.segment_ranges(first_page, num_pages) {
+            self.segments[segment].flush_range_async(first_page, num_pages)?;
+        }
+        Ok(())
+    }
+
+    fn advise_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        advice: Advice,
+    ) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        for (segment, first_page, num_pages) in self.segment_ranges(first_page, num_pages) {
+            self.segments[segment].advise_range(first_page, num_pages, advice)?;
+        }
+        Ok(())
+    }
+
+    fn lock_range(
+        &self,
+        first_page: u64,
+        num_pages: u64,
+        lock: bool,
+    ) -> Result<(), MemoryManagerError> {
+        check_page_range(first_page, num_pages, self.num_pages)?;
+        for (segment, first_page, num_pages) in self.segment_ranges(first_page, num_pages) {
+            self.segments[segment].lock_range(first_page, num_pages, lock)?;
+        }
+        Ok(())
+    }
+
+    // Only the segments whose size changes are mapped again
+    fn resize(&mut self, num_pages: u64) -> Result<(), MemoryManagerError> {
+        check_segment_size(num_pages, self.pages_per_segment)?;
+        let num_segments = num_pages.div_ceil(self.pages_per_segment) as usize;
+        if num_segments < self.segments.len() {
+            self.remove_segments_from(num_segments)?;
+        }
+
+        for index in 0..num_segments {
+            let segment_pages =
+                (num_pages - index as u64 * self.pages_per_segment).min(self.pages_per_segment);
+            if index < self.segments.len() {
+                if self.segments[index].num_pages() != segment_pages {
+                    self.segments[index].resize(segment_pages)?;
+                }
+            } else {
+                let file = self.open_segment(index, true)?;
+                set_file_size(&file, segment_pages, self.page_size, &self.logger)?;
+                self.segments.push(MmapStore::map_file(
+                    &self.filename,
+                    file,
+                    segment_pages,
+                    self.page_size,
+                    MapFlags::default(),
+                    self.logger.clone(),
+                )?);
+                info!(
+                    self.logger,
+                    "Created segment {}",
+                    self.segment_filename(index)
+                );
+            }
+        }
+        self.num_pages = num_pages;
+        Ok(())
+    }
+
+    fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    fn set_logger(&mut self, parent: &Logger) {
+        self.logger = logger::file_logger(parent, &self.filename);
+        for segment in &mut self.segments {
+            segment.set_logger(parent);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::error::MemoryManagerError;
+use std::fmt;
+
+// Unsigned integers of 1 to 8 bytes, for the fields stored on disk. Page ids take 6 bytes
+// (U48), version numbers 5 (U40) and history offsets 3 (U24), the file header uses the
+// usual widths (U8 to U64). All of them are little endian.
+// A u64 is converted with try_from, a value that doesn't fit is an error instead of being
+// truncated when it's written.
+macro_rules! impl_uint {
+    ($name:ident, $num_bytes:expr) => {
+        #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(u64);
+
+        impl $name {
+            pub const BYTES: usize = $num_bytes;
+            pub const MAX: u64 = u64::MAX >> (64 - 8 * $num_bytes);
+            pub const ZERO: $name = $name(0);
+
+            pub fn get(self) -> u64 {
+                self.0
+            }
+
+            pub fn to_le_bytes(self) -> [u8; $num_bytes] {
+                let mut bytes = [0u8; $num_bytes];
+                bytes.copy_from_slice(&self.0.to_le_bytes()[..$num_bytes]);
+                bytes
+            }
+
+            pub fn from_le_bytes(bytes: [u8; $num_bytes]) -> Self {
+                let mut buf = [0u8; 8];
+                buf[..$num_bytes].copy_from_slice(&bytes);
+                $name(u64::from_le_bytes(buf))
+            }
+
+            // Reads the value from the first BYTES bytes of data
+            pub fn read(data: &[u8]) -> Self {
+                Self::from_le_bytes(data[..$num_bytes].try_into().unwrap())
+            }
+
+            // Writes the value to the first BYTES bytes of data
+            pub fn write(self, data: &mut [u8]) {
+                data[..$num_bytes].copy_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl TryFrom<u64> for $name {
+            type Error = MemoryManagerError;
+
+            fn try_from(value: u64) -> Result<Self, Self::Error> {
+                if value > Self::MAX {
+                    return Err(MemoryManagerError::Overflow {
+                        value,
+                        bytes: Self::BYTES,
+                    });
+                }
+                Ok($name(value))
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+impl_uint!(U64, 8);
+impl_uint!(U48, 6);
+impl_uint!(U40, 5);
+impl_uint!(U32, 4);
+impl_uint!(U24, 3);
+impl_uint!(U16, 2);
+impl_uint!(U8, 1);
@@ -0,0 +1,440 @@
+use memory_manager::error::MemoryManagerError;
+use memory_manager::memory_manager::{MemoryManager, META_PAGE_INDEXES};
+use memory_manager::pages::config_page::MemoryLayout;
+use memory_manager::pages::generic_page::GenericPage;
+use memory_manager::pages::page_manager::{GrowthPolicy, PageManager};
+use memory_manager::storage::fault_store::{Fault, FaultDisk, FaultStore};
+use memory_manager::storage::DEFAULT_PAGE_SIZE;
+use std::collections::BTreeMap;
+use std::io;
+
+const NUM_PAGES: u64 = 32;
+// The workload outgrows NUM_PAGES, a crash may leave the file larger than the committed size
+const GROWTH_POLICY: GrowthPolicy = GrowthPolicy::Doubling { max_pages: None };
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    // Allocates pages and fills each one with the position of the op (never 0, like a blank
+    // page)
+    GetFreePages(u64),
+    // Recycles the lowest live pages
+    RecyclePages(usize),
+    // Allocates pages without writing them, so they don't add writes (and faults) to the
+    // workload. Their contents aren't checked.
+    Reserve(u64),
+    // Recycles every reserved page
+    RecycleReserved,
+    Consolidate,
+}
+
+const WORKLOAD: &[Op] = &[
+    Op::GetFreePages(3),
+    Op::Consolidate,
+    Op::GetFreePages(2),
+    Op::RecyclePages(2),
+    Op::Consolidate,
+    Op::GetFreePages(40),
+    Op::Consolidate,
+    // More recycled pages than a free list page holds, the free list takes a chain of pages
+    Op::Reserve(700),
+    Op::Consolidate,
+    Op::RecycleReserved,
+    Op::RecyclePages(4),
+    Op::Consolidate,
+    // Walks past the head of the chain
+    Op::Reserve(690),
+    Op::Consolidate,
+    Op::GetFreePages(4),
+    Op::Consolidate,
+    Op::RecyclePages(3),
+    Op::GetFreePages(1),
+    Op::Consolidate,
+    Op::GetFreePages(2),
+    Op::RecyclePages(1),
+    Op::Consolidate,
+];
+
+// What a committed version must look like after reopening
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    layout: MemoryLayout,
+    free_list: Vec<u64>,
+    // Live page -> byte it was filled with
+    live_pages: BTreeMap<u64, u8>,
+}
+
+// A disk holding a freshly initialized database, nothing can fail yet
+fn initialized_disk() -> Result<FaultDisk, MemoryManagerError> {
+    let disk = FaultDisk::new(NUM_PAGES, DEFAULT_PAGE_SIZE)?;
+    let mut memory = MemoryManager::with_store(FaultStore::open(&disk)?);
+    PageManager::new(&mut memory, NUM_PAGES)?;
+    Ok(disk)
+}
+
+fn snapshot(
+    page_manager: &PageManager,
+    live_pages: &BTreeMap<u64, u8>,
+) -> Result<Snapshot, MemoryManagerError> {
+    Ok(Snapshot {
+        layout: MemoryLayout::from_bytes_at(page_manager.config_page(), 0)?,
+        free_list: page_manager.get_free_list_page_at(0)?,
+        live_pages: live_pages.clone(),
+    })
+}
+
+// Runs the workload on the disk, storing a snapshot of every committed version
+fn run(
+    disk: &FaultDisk,
+    snapshots: &mut BTreeMap<u64, Snapshot>,
+) -> Result<(), MemoryManagerError> {
+    let mut memory = MemoryManager::with_store(FaultStore::open(disk)?);
+    let mut page_manager = PageManager::open(&mut memory)?;
+    page_manager.growth_policy = GROWTH_POLICY;
+    let mut live_pages = BTreeMap::new();
+    let mut reserved_pages = vec![];
+
+    for (i, op) in WORKLOAD.iter().enumerate() {
+        match *op {
+            Op::GetFreePages(num) => {
+                for page in page_manager.get_free_pages(num, true)? {
+                    let mut generic_page =
+                        page_manager.memory().get_page_mut::<GenericPage>(page)?;
+                    generic_page.page_mut().data_mut().fill(i as u8 + 1);
+                    live_pages.insert(page, i as u8 + 1);
+                }
+            }
+            Op::RecyclePages(num) => {
+                let mut pages: Vec<u64> = live_pages.keys().copied().take(num).collect();
+                for page in &pages {
+                    live_pages.remove(page);
+                }
+                page_manager.recyle_pages(&mut pages);
+            }
+            Op::Reserve(num) => {
+                reserved_pages.extend(page_manager.get_free_pages(num, true)?);
+            }
+            Op::RecycleReserved => page_manager.recyle_pages(&mut reserved_pages),
+            Op::Consolidate => {
+                page_manager.consolidate_state()?;
+                let snapshot = snapshot(&page_manager, &live_pages)?;
+                snapshots.insert(snapshot.layout.version_number, snapshot);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reopens the disk after a crash and checks it holds one of the committed versions. Returns
+// whether the file had grown past the size of the version recovered.
+fn check_recovery(
+    disk: &FaultDisk,
+    expected: &BTreeMap<u64, Snapshot>,
+    last_committed: u64,
+    fault: Fault,
+) -> Result<bool, MemoryManagerError> {
+    let disk_pages = disk.num_pages();
+    let mut memory = MemoryManager::with_store(FaultStore::open(disk)?);
+    let mut page_manager = PageManager::open(&mut memory)?;
+    page_manager.growth_policy = GROWTH_POLICY;
+
+    // The commit in flight may have made it if the crash came after its root
+    let layout = MemoryLayout::from_bytes_at(page_manager.config_page(), 0)?;
+    let version = layout.version_number;
+    assert!(
+        version == last_committed || version == last_committed + 1,
+        "{:?}: recovered version {}, last committed {}",
+        fault,
+        version,
+        last_committed
+    );
+    let snapshot = &expected[&version];
+    assert_eq!(layout, snapshot.layout, "{:?}", fault);
+    assert_eq!(
+        page_manager.get_free_list_page_at(0)?,
+        snapshot.free_list,
+        "{:?}",
+        fault
+    );
+    for (&page, &marker) in &snapshot.live_pages {
+        let generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        assert!(
+            generic_page.data().iter().all(|&byte| byte == marker),
+            "{:?}: page {} lost its contents",
+            fault,
+            page
+        );
+    }
+
+    let report = page_manager.check()?;
+    assert!(report.is_ok(), "{:?}: {:?}", fault, report.issues);
+
+    // The pages past the committed size are ignored, open doesn't resize the file
+    assert_eq!(page_manager.memory().num_pages(), disk_pages, "{:?}", fault);
+
+    // And we can keep working on it
+    page_manager.get_free_pages(1, true)?;
+    page_manager.consolidate_state()?;
+    Ok(disk_pages > layout.total_allocated_pages)
+}
+
+#[test]
+fn test_fault_store_drops_unflushed_writes() -> io::Result<()> {
+    let disk = initialized_disk()?;
+    let writes = disk.num_writes();
+    {
+        let mut memory = MemoryManager::with_store(FaultStore::open(&disk)?);
+        let mut page_manager = PageManager::open(&mut memory)?;
+        let page = page_manager.get_free_pages(1, true)?[0];
+        page_manager
+            .memory()
+            .get_page_mut::<GenericPage>(page)?
+            .page_mut()
+            .data_mut()
+            .fill(0xAB);
+    }
+    // Nothing was flushed, the disk is as it was
+    assert_eq!(disk.num_writes(), writes);
+    let mut memory = MemoryManager::with_store(FaultStore::open(&disk)?);
+    let page_manager = PageManager::open(&mut memory)?;
+    assert_eq!(page_manager.config_page().get_version_number(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_fault_store_torn_write() -> io::Result<()> {
+    let disk = initialized_disk()?;
+    let image = disk.image();
+    let memory = MemoryManager::with_store(FaultStore::open(&disk)?);
+    let page = 5;
+    memory
+        .get_page_mut::<GenericPage>(page)?
+        .page_mut()
+        .data_mut()
+        .fill(0xCD);
+    disk.set_fault(Some(Fault::TornWrite {
+        write: 0,
+        bytes: 100,
+    }));
+    assert!(memory.flush().is_err());
+    assert!(disk.crashed());
+    // Once crashed nothing else reaches the disk
+    assert!(memory.flush().is_err());
+    assert_eq!(disk.writes().last(), Some(&page));
+
+    let start = (page * DEFAULT_PAGE_SIZE) as usize;
+    let end = start + DEFAULT_PAGE_SIZE as usize;
+    let torn_image = disk.image();
+    assert!(torn_image[start..start + 100]
+        .iter()
+        .all(|&byte| byte == 0xCD));
+    assert_eq!(torn_image[start + 100..end], image[start + 100..end]);
+
+    Ok(())
+}
+
+// Fills pages, recycles some and commits, returning the pages written by the commit in order
+fn commit(disk: &FaultDisk, async_root: bool) -> Result<Vec<u64>, MemoryManagerError> {
+    let mut memory = MemoryManager::with_store(FaultStore::open(disk)?);
+    let mut page_manager = PageManager::open(&mut memory)?;
+    let mut pages = page_manager.get_free_pages(4, true)?;
+    for &page in &pages {
+        let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        generic_page.page_mut().data_mut().fill(page as u8);
+    }
+    page_manager.recyle_pages(&mut pages.split_off(2));
+
+    let first_write = disk.num_writes();
+    if async_root {
+        page_manager.consolidate_state_async()?;
+    } else {
+        page_manager.consolidate_state()?;
+    }
+    Ok(disk.writes()[first_write..].to_vec())
+}
+
+#[test]
+fn test_commit_writes_root_last() -> Result<(), MemoryManagerError> {
+    for async_root in [false, true] {
+        let disk = initialized_disk()?;
+        let writes = commit(&disk, async_root)?;
+        // The data pages, the free list page and the temporal config page, then the root
+        let (root, pages) = writes.split_last().unwrap();
+        assert!(META_PAGE_INDEXES.contains(root), "{:?}", writes);
+        assert!(pages.len() >= 4, "{:?}", writes);
+        assert!(pages.iter().all(|page| !META_PAGE_INDEXES.contains(page)));
+
+        // The root waits for the barrier: if any page of the first phase doesn't make it, the
+        // root is never written
+        for write in 0..pages.len() {
+            let disk = initialized_disk()?;
+            let first_write = disk.num_writes();
+            disk.set_fault(Some(Fault::DroppedWrite { write }));
+            assert!(commit(&disk, async_root).is_err());
+            assert!(disk.writes()[first_write..]
+                .iter()
+                .all(|page| !META_PAGE_INDEXES.contains(page)));
+        }
+    }
+
+    Ok(())
+}
+
+// A committed database with free pages below live ones, and pages recycled after the commit
+struct VacuumDisk {
+    disk: FaultDisk,
+    // Live page of the commit -> byte it was filled with
+    live_pages: BTreeMap<u64, u8>,
+    // Live in the commit, recycled since
+    pending: Vec<u64>,
+    version: u64,
+}
+
+fn vacuum_disk() -> Result<VacuumDisk, MemoryManagerError> {
+    let disk = initialized_disk()?;
+    let mut memory = MemoryManager::with_store(FaultStore::open(&disk)?);
+    let mut page_manager = PageManager::open(&mut memory)?;
+    let mut live_pages = BTreeMap::new();
+    for page in page_manager.get_free_pages(12, true)? {
+        let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        generic_page.page_mut().data_mut().fill(page as u8);
+        live_pages.insert(page, page as u8);
+    }
+    let mut pages: Vec<u64> = live_pages.keys().copied().take(6).collect();
+    for page in &pages {
+        live_pages.remove(page);
+    }
+    page_manager.recyle_pages(&mut pages);
+    page_manager.consolidate_state()?;
+
+    let pending = live_pages.keys().copied().take(2).collect();
+    let version = page_manager.config_page().get_version_number();
+    Ok(VacuumDisk {
+        disk,
+        live_pages,
+        pending,
+        version,
+    })
+}
+
+// Recycles the pending pages and vacuums, returning the pages moved
+fn vacuum(disk: &FaultDisk, pending: &[u64]) -> Result<BTreeMap<u64, u64>, MemoryManagerError> {
+    let mut memory = MemoryManager::with_store(FaultStore::open(disk)?);
+    let mut page_manager = PageManager::open(&mut memory)?;
+    page_manager.recyle_pages(&mut pending.to_vec());
+    let mut relocations = BTreeMap::new();
+    page_manager.vacuum(2, |_, old_page, new_page| {
+        relocations.insert(old_page, new_page);
+        Ok(())
+    })?;
+    Ok(relocations)
+}
+
+#[test]
+fn test_vacuum_crash_recovery() -> Result<(), MemoryManagerError> {
+    let VacuumDisk { disk, pending, .. } = vacuum_disk()?;
+    let first_write = disk.num_writes();
+    let relocations = vacuum(&disk, &pending)?;
+    let num_writes = disk.num_writes() - first_write;
+    assert!(!relocations.is_empty());
+
+    for write in 0..num_writes {
+        for fault in [
+            Fault::Crash { write },
+            Fault::TornWrite {
+                write,
+                bytes: DEFAULT_PAGE_SIZE as usize / 2,
+            },
+            Fault::DroppedWrite { write },
+        ] {
+            let VacuumDisk {
+                disk,
+                live_pages,
+                pending,
+                version,
+            } = vacuum_disk()?;
+            disk.set_fault(Some(fault));
+            assert!(vacuum(&disk, &pending).is_err(), "{:?}", fault);
+
+            // Either the last commit, untouched, or the vacuum
+            let mut memory = MemoryManager::with_store(FaultStore::open(&disk)?);
+            let page_manager = PageManager::open(&mut memory)?;
+            let recovered = page_manager.config_page().get_version_number();
+            assert!(
+                recovered == version || recovered == version + 1,
+                "{:?}: recovered version {}, last committed {}",
+                fault,
+                recovered,
+                version
+            );
+            for (&page, &marker) in &live_pages {
+                let page = if recovered == version {
+                    page
+                } else if pending.contains(&page) {
+                    continue;
+                } else {
+                    relocations.get(&page).copied().unwrap_or(page)
+                };
+                let generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+                assert!(
+                    generic_page.data().iter().all(|&byte| byte == marker),
+                    "{:?}: page {} lost its contents",
+                    fault,
+                    page
+                );
+            }
+            let report = page_manager.check()?;
+            assert!(report.is_ok(), "{:?}: {:?}", fault, report.issues);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_crash_recovery() -> Result<(), MemoryManagerError> {
+    let disk = initialized_disk()?;
+    let mut expected = BTreeMap::new();
+    // The version we start from is the one we recover if the first commit never makes it
+    let version = {
+        let mut memory = MemoryManager::with_store(FaultStore::open(&disk)?);
+        let page_manager = PageManager::open(&mut memory)?;
+        let snapshot = snapshot(&page_manager, &BTreeMap::new())?;
+        let version = snapshot.layout.version_number;
+        expected.insert(version, snapshot);
+        version
+    };
+
+    // Reference run, counting the writes of the workload
+    let first_write = disk.num_writes();
+    run(&disk, &mut expected)?;
+    let num_writes = disk.num_writes() - first_write;
+    assert_eq!(expected.len(), 10);
+    // Crashes between growing the file and committing its new size
+    let mut grown = 0;
+
+    for write in 0..num_writes {
+        for fault in [
+            Fault::Crash { write },
+            Fault::TornWrite {
+                write,
+                bytes: DEFAULT_PAGE_SIZE as usize / 2,
+            },
+            Fault::DroppedWrite { write },
+        ] {
+            let disk = initialized_disk()?;
+            disk.set_fault(Some(fault));
+            let mut committed = BTreeMap::new();
+            assert!(run(&disk, &mut committed).is_err(), "{:?}", fault);
+            assert!(disk.crashed(), "{:?}", fault);
+
+            let last_committed = committed.keys().last().copied().unwrap_or(version);
+            if check_recovery(&disk, &expected, last_committed, fault)? {
+                grown += 1;
+            }
+        }
+    }
+    assert!(grown > 0);
+
+    Ok(())
+}
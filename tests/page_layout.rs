@@ -0,0 +1,158 @@
+use memory_manager::error::MemoryManagerError;
+use memory_manager::memory_manager::{MemoryManager, FIRST_DATA_PAGE_INDEX};
+use memory_manager::pages::config_page::{
+    self, ConfigPageRef, SuperblockRef, FILE_HEADER_BYTES, FORMAT_VERSION, MAGIC,
+};
+use memory_manager::pages::free_list_page::{FreeListPage, FreeListPageRef};
+use memory_manager::pages::from_slice::{FromSlice, FromSliceRef};
+use memory_manager::u48::{U24, U40, U48};
+
+// An application page: a fixed header and a history of roots after it
+memory_manager::page_layout! {
+    pub struct IndexPage, IndexPageRef {
+        start: 8,
+        history: check_slot,
+        debug: [magic],
+        fields {
+            root: U48,
+            version: U40,
+            height: U24,
+        }
+    }
+}
+
+impl<'a> IndexPageRef<'a> {
+    fn magic(&self) -> u8 {
+        self.data[0]
+    }
+}
+
+// A page without a history, with a single field at the beginning
+memory_manager::page_layout! {
+    struct CounterPage, CounterPageRef {
+        start: 0,
+        fields {
+            counter: U48,
+        }
+    }
+}
+
+#[test]
+fn test_page_layout_accessors() -> Result<(), MemoryManagerError> {
+    assert_eq!(IndexPage::START, 8);
+    assert_eq!(IndexPage::RECORD_BYTES, 14);
+    assert_eq!(CounterPageRef::RECORD_BYTES, 6);
+
+    let memory = MemoryManager::in_memory(4)?;
+    {
+        let mut guard = memory.get_page_mut::<IndexPage>(2)?;
+        let mut page = guard.page_mut();
+        page.data_mut()[0] = 0x2A;
+        page.set_root(0x0102_0304_0506)?;
+        page.set_version(7)?;
+        page.set_height(3)?;
+        // The fields are stored one after the other, from start on
+        assert_eq!(
+            page.data()[8..22],
+            [6, 5, 4, 3, 2, 1, 7, 0, 0, 0, 0, 3, 0, 0]
+        );
+
+        // Values that don't fit are refused and the field keeps its value
+        assert!(matches!(
+            page.set_height(1 << 24),
+            Err(MemoryManagerError::Overflow { bytes: 3, .. })
+        ));
+        assert!(page.set_root(U48::MAX + 1).is_err());
+        assert_eq!(page.get_height(), 3);
+        assert_eq!(page.get_root(), 0x0102_0304_0506);
+
+        // An older record in slot 1
+        page.data_mut().copy_within(8..22, 22);
+        page.set_version(8)?;
+        page.update_checksum();
+    }
+
+    let page = memory.get_page_ref::<IndexPageRef>(2)?;
+    page.verify_checksum(2)?;
+    assert_eq!(page.get_version(), 8);
+    assert_eq!(page.get_version_at(1)?, 7);
+    assert_eq!(page.get_root_at(1)?, 0x0102_0304_0506);
+    assert_eq!(page.get_history_slots(), (4096 - 8 - 4) / 14);
+    let max_version = page.get_history_slots() - 1;
+    assert!(matches!(
+        page.get_height_at(max_version + 1),
+        Err(MemoryManagerError::VersionOutOfRange { version, .. }) if version == max_version + 1
+    ));
+    assert_eq!(
+        format!("{:?}", *page),
+        "IndexPageRef { root: 1108152157446, version: 8, height: 3, magic: 42 }"
+    );
+    drop(page);
+
+    let mut counter = memory.get_page_mut::<CounterPage>(3)?;
+    counter.page_mut().set_counter(5)?;
+    assert_eq!(CounterPageRef::from(&counter).get_counter(), 5);
+    assert_eq!(format!("{:?}", *counter), "CounterPage { counter: 5 }");
+
+    Ok(())
+}
+
+#[test]
+fn test_page_layout_metadata_pages() -> Result<(), MemoryManagerError> {
+    let mut data = vec![0u8; 4096];
+    let mut free_list_page = FreeListPage::from_slice(&mut data);
+    free_list_page.set_free_list_page_next(9)?;
+    free_list_page.set_chain_free_pages(2)?;
+    free_list_page.set_recycled_pages_list(&[4, 5])?;
+    assert_eq!(
+        format!("{:?}", free_list_page),
+        "FreeListPage { free_list_page_next: 9, chain_free_pages: 2, reserved: 0, \
+         first_free_pages: [4, 5, 0] }"
+    );
+
+    // The file header fields, one after the other from byte 0
+    let mut data = vec![0u8; 4096];
+    config_page::write_superblock(&mut data, 0x2000)?;
+    config_page::set_pages_per_segment(&mut data, 7)?;
+    assert_eq!(FILE_HEADER_BYTES, 32);
+    assert_eq!(data[0..4], [0, 0x20, 0, 0]);
+    assert_eq!(data[4..12], MAGIC);
+    assert_eq!(data[12..16], [FORMAT_VERSION as u8, 0, 0, 0]);
+    assert_eq!(data[16..24], [0; 8]);
+    assert_eq!(data[24..32], [7, 0, 0, 0, 0, 0, 0, 0]);
+    let superblock = SuperblockRef::from_slice_ref(&data);
+    assert_eq!(superblock.get_page_size(), 0x2000);
+    assert_eq!(superblock.get_pages_per_segment(), 7);
+    config_page::check_superblock(&data)?;
+
+    // The config header starts after the file header
+    let mut data = vec![0u8; 4096];
+    data[32..38].copy_from_slice(&[3, 0, 0, 0, 0, 0]);
+    let config_page = ConfigPageRef::from_slice_ref(&data);
+    assert_eq!(config_page.get_total_allocated_pages(), 3);
+    assert_eq!(ConfigPageRef::RECORD_BYTES, 32);
+    assert!(config_page.get_offset_at(1).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_init_free_list_pages() -> Result<(), MemoryManagerError> {
+    let memory = MemoryManager::in_memory(10)?;
+    let mut scratch = vec![0u8; 4096];
+    FreeListPage::from_slice(&mut scratch).init_free_list_pages(&memory, 10)?;
+
+    // The meta pages are left alone, the chain starts at the first data page
+    for index in memory_manager::memory_manager::META_PAGE_INDEXES {
+        assert!(memory.get_page(index)?.data().iter().all(|&byte| byte == 0));
+    }
+    let free_list_page = memory.get_page_ref::<FreeListPageRef>(FIRST_DATA_PAGE_INDEX)?;
+    free_list_page.verify_checksum(FIRST_DATA_PAGE_INDEX)?;
+    assert_eq!(
+        free_list_page.get_recycled_pages_list()?,
+        (3..10).collect::<Vec<_>>()
+    );
+    assert_eq!(free_list_page.get_free_list_page_next(), 0);
+
+    Ok(())
+}
@@ -0,0 +1,787 @@
+use memory_manager::error::MemoryManagerError;
+use memory_manager::memory_manager::{
+    Advice, MemoryManager, MemoryManagerOptions, PageGuard, PageRef,
+};
+use memory_manager::pages::config_page::{ConfigPageRef, MemoryLayout};
+use memory_manager::pages::free_list_page::FreeListPageRef;
+use memory_manager::pages::generic_page::{GenericPage, GenericPageRef};
+use memory_manager::pages::page_manager::{GrowthPolicy, PageManager};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+mod common;
+use common::TempDir;
+
+#[test]
+fn test_file_io_store_persists_pages() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_file_io_store_persists_pages.bin");
+    let num_pages = 4u64;
+    {
+        let mut memory: MemoryManager = MemoryManager::new_file_io(filename, num_pages)?;
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+        page_manager.growth_policy = GrowthPolicy::Doubling { max_pages: None };
+
+        let pages = page_manager.get_free_pages(4, true)?;
+        for &page in &pages {
+            let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+            generic_page.page_mut().data_mut().fill(page as u8);
+        }
+        page_manager.recyle_pages(&mut vec![pages[0]]);
+        page_manager.consolidate_state()?;
+    }
+    // The free list page doesn't fit either
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * 16);
+
+    // Read it back through the memory mapped store
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    assert_eq!(page_manager.total_allocated_pages, 16);
+    assert_eq!(page_manager.last_used_page, 8);
+    for page in 4..=6 {
+        let generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        assert!(generic_page.data().iter().all(|&byte| byte == page as u8));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_file_io_store_releases_pages() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_file_io_store_releases_pages.bin");
+    let memory: MemoryManager = MemoryManager::new_file_io(filename, 16)?;
+
+    for page in 2..10 {
+        memory
+            .get_page_mut::<GenericPage>(page)?
+            .page_mut()
+            .data_mut()
+            .fill(page as u8);
+    }
+    memory.get_page(12)?;
+    assert_eq!(memory.num_cached_pages(), 9);
+
+    // Only the borrowed pages stay cached after a flush
+    let mut held = memory.get_page_mut::<GenericPage>(3)?;
+    let read = memory.get_page(4)?;
+    memory.flush()?;
+    assert_eq!(memory.num_cached_pages(), 2);
+    held.page_mut().data_mut()[0] = 0xff;
+    assert_eq!(read.data()[1], 4);
+    drop(read);
+    memory.mark_dirty(3);
+    drop(held);
+    memory.flush_async()?;
+    assert_eq!(memory.num_cached_pages(), 0);
+
+    // Released pages are read back from the file
+    for page in 2..10 {
+        let generic_page = memory.get_page(page)?;
+        let first = if page == 3 { 0xff } else { page as u8 };
+        assert_eq!(generic_page.data()[0], first);
+        assert!(generic_page.data()[1..]
+            .iter()
+            .all(|&byte| byte == page as u8));
+    }
+    memory.flush_all()?;
+    assert_eq!(memory.num_cached_pages(), 0);
+    drop(memory);
+
+    // Stores that map the file don't cache anything
+    let memory: MemoryManager = MemoryManager::open(filename)?;
+    memory.get_page(2)?;
+    assert_eq!(memory.num_cached_pages(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_file_io_store_out_of_bounds() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_file_io_store_out_of_bounds.bin");
+    let memory: MemoryManager = MemoryManager::new_file_io(filename, 2)?;
+    let result = memory.get_page_mut::<GenericPage>(2);
+
+    assert_eq!(result.unwrap_err().to_string(), "Index 2 is out of bounds");
+    Ok(())
+}
+
+#[test]
+fn test_in_memory_store_grows_and_shrinks() -> io::Result<()> {
+    let num_pages = 4u64;
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages)?;
+    let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+    page_manager.growth_policy = GrowthPolicy::Doubling { max_pages: None };
+
+    let pages = page_manager.get_free_pages(2, true)?;
+    for &page in &pages {
+        let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        generic_page.page_mut().data_mut().fill(page as u8);
+    }
+    // Growing copies the pages into the new mapping
+    page_manager.get_free_pages(10, true)?;
+    assert_eq!(page_manager.total_allocated_pages, 16);
+    for &page in &pages {
+        let generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        assert!(generic_page.data().iter().all(|&byte| byte == page as u8));
+    }
+    page_manager.recyle_pages(&mut (5..15).collect());
+    page_manager.consolidate_state()?;
+
+    let mut relocations = vec![];
+    page_manager.vacuum(2, |_, old_page, new_page| {
+        relocations.push((old_page, new_page));
+        Ok(())
+    })?;
+    // Page 2 holds the free list of the first version, still in the history of the last
+    // commit: it's kept where it is and freed
+    assert_eq!(relocations, vec![]);
+    assert_eq!(page_manager.total_allocated_pages, 8);
+    assert_eq!(page_manager.memory().num_pages(), 8);
+    assert_eq!(page_manager.recycled_pages, vec![2]);
+    for (page, marker) in [(3, 3u8), (4, 4u8)] {
+        let generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        assert!(generic_page.data().iter().all(|&byte| byte == marker));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_flush_only_dirty_pages() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_flush_only_dirty_pages.bin");
+    let memory: MemoryManager = MemoryManager::new(filename, 16)?;
+
+    for page in [1, 2, 3, 7] {
+        memory
+            .get_page_mut::<GenericPage>(page)?
+            .page_mut()
+            .data_mut()[0] = page as u8;
+    }
+    memory.get_page(9)?;
+    assert_eq!(memory.num_dirty_pages(), 4);
+    assert_eq!(memory.flush()?, 4 * 4096);
+    assert_eq!(memory.num_dirty_pages(), 0);
+    assert_eq!(memory.flush()?, 0);
+
+    memory.mark_dirty(5);
+    assert_eq!(memory.flush_async()?, 4096);
+    assert_eq!(memory.num_dirty_pages(), 0);
+
+    drop(memory);
+
+    Ok(())
+}
+
+#[test]
+fn test_consolidate_state_reports_flushed_bytes() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_consolidate_state_reports_flushed_bytes.bin");
+    let num_pages = 1024u64;
+    {
+        let mut memory: MemoryManager = MemoryManager::new_file_io(filename, num_pages)?;
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+        let pages = page_manager.get_free_pages(100, true)?;
+        page_manager
+            .memory()
+            .get_page_mut::<GenericPage>(pages[50])?
+            .page_mut()
+            .data_mut()
+            .fill(0xaa);
+
+        // config page, temporal config page, the new free list page (holding the temporal
+        // config page) and the page we wrote
+        assert_eq!(page_manager.consolidate_state()?, 4 * 4096);
+        // The temporal config page is reused and the free list doesn't change, so only the
+        // config page and the temporal one
+        assert_eq!(page_manager.consolidate_state_async()?, 2 * 4096);
+    }
+
+    // Written through pwrite, so the flushed pages must be in the file
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    assert_eq!(page_manager.config_page().get_version_number(), 3);
+    assert!(page_manager
+        .memory()
+        .get_page(53)?
+        .data()
+        .iter()
+        .all(|&b| b == 0xaa));
+
+    Ok(())
+}
+
+#[test]
+fn test_writer_lock() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_writer_lock.bin");
+    let locked = format!("database locked by pid {}", std::process::id());
+    let memory: MemoryManager = MemoryManager::new(filename, 4)?;
+
+    // A second writer or a reader has to wait for the writer to go away
+    let err = MemoryManager::new(filename, 4).unwrap_err();
+    assert!(matches!(err, MemoryManagerError::Locked { pid } if pid == std::process::id()));
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(err.to_string(), locked);
+    // Callers working with io::Error keep the kind and the message
+    let io_err = io::Error::from(err);
+    assert_eq!(io_err.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(io_err.to_string(), locked);
+    assert_eq!(
+        MemoryManager::open_file_io(filename)
+            .unwrap_err()
+            .to_string(),
+        locked
+    );
+    assert_eq!(
+        MemoryManager::open_read_only(filename)
+            .unwrap_err()
+            .to_string(),
+        locked
+    );
+    // The failed attempt didn't resize the file
+    assert_eq!(fs::metadata(filename)?.len(), 4 * 4096);
+    drop(memory);
+
+    // Readers share the lock but keep writers out
+    let reader = MemoryManager::open_read_only(filename)?;
+    let other_reader = MemoryManager::open_read_only(filename)?;
+    assert_eq!(
+        MemoryManager::open(filename).unwrap_err().to_string(),
+        locked
+    );
+    drop(reader);
+    drop(other_reader);
+
+    assert!(MemoryManager::open(filename).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_advise() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_advise.bin");
+    let file_io_filename = &dir.file("test_advise_file_io.bin");
+    let advices = [
+        Advice::Sequential,
+        Advice::Random,
+        Advice::WillNeed,
+        Advice::DontNeed,
+        Advice::Normal,
+    ];
+    for memory in [
+        MemoryManager::new(filename, 8)?,
+        MemoryManager::new_file_io(file_io_filename, 8)?,
+        MemoryManager::in_memory(8)?,
+    ] {
+        memory
+            .get_page_mut::<GenericPage>(3)?
+            .page_mut()
+            .data_mut()
+            .fill(0x33);
+        for advice in advices {
+            memory.advise(0, 8, advice)?;
+        }
+        // Dropping the pages from memory doesn't lose their contents
+        assert!(memory.get_page(3)?.data().iter().all(|&byte| byte == 0x33));
+        assert_eq!(
+            memory.advise(6, 3, Advice::WillNeed).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_options_lock_metadata() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_options_lock_metadata.bin");
+    let num_pages = 8u64;
+    let mut memory: MemoryManager = MemoryManagerOptions::new()
+        .populate(true)
+        .huge_pages(true)
+        .lock_metadata(true)
+        .open(filename, num_pages)?;
+    assert!(memory.locks_metadata());
+    // The two meta pages
+    assert_eq!(memory.num_locked_pages(), 2);
+    {
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+        page_manager.growth_policy = GrowthPolicy::Doubling { max_pages: None };
+        // The meta pages and the free list page
+        assert_eq!(page_manager.memory().num_locked_pages(), 3);
+
+        for _ in 0..4 {
+            let mut pages = page_manager.get_free_pages(3, true)?;
+            page_manager.recyle_pages(&mut pages);
+            page_manager.consolidate_state()?;
+            assert_eq!(page_manager.memory().num_locked_pages(), 3);
+        }
+        // Growing maps the file again, the locks move to the new mapping
+        page_manager.get_free_pages(20, false)?;
+        assert_eq!(page_manager.total_allocated_pages, 32);
+        assert_eq!(page_manager.memory().num_locked_pages(), 3);
+    }
+    memory.unlock_pages(0, memory.num_pages())?;
+    assert_eq!(memory.num_locked_pages(), 0);
+    drop(memory);
+
+    Ok(())
+}
+
+#[test]
+fn test_options_create_truncate() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_options_create_truncate.bin");
+    let err = MemoryManagerOptions::new()
+        .create(false)
+        .open(filename, 4)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(!std::path::Path::new(filename).exists());
+
+    {
+        let memory = MemoryManagerOptions::new()
+            .page_size(0x2000)
+            .open(filename, 4)?;
+        memory
+            .get_page_mut::<GenericPage>(2)?
+            .page_mut()
+            .data_mut()
+            .fill(0xff);
+        memory.flush()?;
+    }
+    {
+        // Reusing the file keeps its contents and its length
+        let memory = MemoryManagerOptions::new()
+            .create(false)
+            .page_size(0x2000)
+            .open(filename, 8)?;
+        assert_eq!(memory.num_pages(), 4);
+        assert!(memory.get_page(2)?.data().iter().all(|&byte| byte == 0xff));
+    }
+    assert_eq!(fs::metadata(filename)?.len(), 0x2000 * 4);
+    let err = MemoryManagerOptions::new()
+        .create(false)
+        .open(filename, 4)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Page size mismatch: file page size: 8192, requested page size: 4096"
+    );
+    let memory = MemoryManagerOptions::new()
+        .truncate(true)
+        .page_size(0x2000)
+        .open(filename, 4)?;
+    assert_eq!(memory.page_size(), 0x2000);
+    assert!(memory.get_page(2)?.data().iter().all(|&byte| byte == 0));
+    drop(memory);
+
+    Ok(())
+}
+
+#[test]
+fn test_segmented_store() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_segmented_store.bin");
+    let segment = |index: u64| format!("{}.{}", filename, index);
+    let pages_per_segment = 4u64;
+    {
+        let mut memory: MemoryManager =
+            MemoryManager::new_segmented(filename, 4, pages_per_segment)?;
+        assert!(!std::path::Path::new(&segment(1)).exists());
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 4)?;
+        page_manager.growth_policy = GrowthPolicy::Doubling { max_pages: None };
+
+        // Segments 1 to 3 are created as the memory grows
+        let pages = page_manager.get_free_pages(10, true)?;
+        assert_eq!(page_manager.total_allocated_pages, 16);
+        for &page in &pages {
+            let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+            generic_page.page_mut().data_mut().fill(page as u8);
+        }
+        page_manager.consolidate_state()?;
+    }
+    for index in 1..4 {
+        assert_eq!(
+            fs::metadata(segment(index))?.len(),
+            4096 * pages_per_segment
+        );
+    }
+    assert!(!std::path::Path::new(&segment(4)).exists());
+
+    // The segment size is recorded in the file, a different one would read the wrong pages
+    assert_eq!(
+        MemoryManagerOptions::new()
+            .pages_per_segment(8)
+            .create(false)
+            .open(filename, 0)
+            .unwrap_err()
+            .to_string(),
+        "Segment size mismatch: file pages per segment: 4, requested pages per segment: 8"
+    );
+    assert_eq!(
+        MemoryManager::new_segmented(filename, 16, 2)
+            .unwrap_err()
+            .to_string(),
+        "Segment size mismatch: file pages per segment: 4, requested pages per segment: 2"
+    );
+
+    {
+        let mut memory: MemoryManager = MemoryManager::open_segmented(filename)?;
+        assert_eq!(memory.num_pages(), 16);
+        let mut page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+        for page in 3..13 {
+            let generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+            assert!(generic_page.data().iter().all(|&byte| byte == page as u8));
+        }
+
+        // Shrinking deletes the segments that are no longer needed
+        page_manager.recyle_pages(&mut (3..13).collect());
+        page_manager.consolidate_state()?;
+        page_manager.vacuum(0, |_, _, _| Ok(()))?;
+        assert_eq!(page_manager.total_allocated_pages, 4);
+    }
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * 4);
+    assert!(!std::path::Path::new(&segment(1)).exists());
+
+    // A segment missing in the middle can't be mistaken for the end of the database
+    let memory = MemoryManager::new_segmented(filename, 9, pages_per_segment)?;
+    drop(memory);
+    fs::remove_file(segment(1))?;
+    fs::write(segment(1), [])?;
+    assert_eq!(
+        MemoryManager::open_segmented(filename).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_regular_database_as_segmented() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_regular_database_as_segmented.bin");
+    {
+        // Version 3 lives in meta page 0, the one holding the file header
+        let mut memory: MemoryManager = MemoryManager::new(filename, 8)?;
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 8)?;
+        page_manager.consolidate_state()?;
+        page_manager.consolidate_state()?;
+        assert_eq!(page_manager.config_page().index(), 0);
+        assert_eq!(page_manager.config_page().get_version_number(), 3);
+    }
+
+    // A regular database records no segment size, it's refused without touching the file
+    for result in [
+        MemoryManager::open_segmented(filename),
+        MemoryManager::new_segmented(filename, 8, 4),
+    ] {
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Not a segmented database: the file header records no segment size"
+        );
+    }
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * 8);
+
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    assert_eq!(page_manager.config_page().index(), 0);
+    assert_eq!(page_manager.config_page().get_version_number(), 3);
+
+    Ok(())
+}
+
+// Fails to compile if a store (or the borrow tracking) stops being thread safe
+#[test]
+fn test_memory_manager_is_send_sync() -> io::Result<()> {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<MemoryManager>();
+    assert_send_sync::<PageRef<GenericPageRef>>();
+    assert_send_sync::<PageGuard<GenericPage>>();
+
+    // Readers on several threads share the memory manager
+    let memory: MemoryManager = MemoryManager::in_memory(4)?;
+    memory
+        .get_page_mut::<GenericPage>(3)?
+        .page_mut()
+        .data_mut()
+        .fill(0x33);
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                let page = memory.get_page(3).unwrap();
+                assert!(page.data().iter().all(|&byte| byte == 0x33));
+            });
+        }
+    });
+    assert_eq!(memory.num_borrowed_pages(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_page_borrows() -> io::Result<()> {
+    let memory: MemoryManager = MemoryManager::in_memory(4)?;
+
+    let mut page = memory.get_page_mut::<GenericPage>(1)?;
+    assert_eq!(
+        memory
+            .get_page_mut::<GenericPage>(1)
+            .unwrap_err()
+            .to_string(),
+        "Page 1 is already mutably borrowed"
+    );
+    assert!(memory.get_page(1).is_err());
+    // Other pages are still available
+    let other_page = memory.get_page_mut::<GenericPage>(2)?;
+    assert_eq!(memory.num_borrowed_pages(), 2);
+    page.page_mut().data_mut().fill(1);
+    drop(page);
+    drop(other_page);
+    assert_eq!(memory.num_borrowed_pages(), 0);
+
+    // Any number of shared views, but no exclusive one while they live
+    let first = memory.get_page(1)?;
+    let second = memory.get_page(1)?;
+    assert_eq!(
+        memory
+            .get_page_mut::<GenericPage>(1)
+            .unwrap_err()
+            .to_string(),
+        "Page 1 is already borrowed"
+    );
+    assert_eq!(first.data(), second.data());
+    drop(first);
+    assert!(memory.get_page_mut::<GenericPage>(1).is_err());
+    drop(second);
+    assert!(memory.get_page_mut::<GenericPage>(1).is_ok());
+
+    assert_eq!(
+        memory.get_page(4).unwrap_err().to_string(),
+        "Index 4 is out of bounds"
+    );
+    assert_eq!(memory.num_borrowed_pages(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_page_refs() -> io::Result<()> {
+    let mut memory: MemoryManager = MemoryManager::in_memory(16)?;
+    let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 16)?;
+    let mut pages = page_manager.get_free_pages(2, true)?;
+    page_manager.recyle_pages(&mut pages);
+    page_manager.consolidate_state()?;
+    let recycled_pages = page_manager.get_free_list_page_at(0)?;
+    let config_page_index = page_manager.config_page().index();
+    drop(page_manager);
+    assert_eq!(memory.num_dirty_pages(), 0);
+
+    let config_page = memory.get_page_ref::<ConfigPageRef>(config_page_index)?;
+    let config_view = ConfigPageRef::from(&config_page);
+    let layout = MemoryLayout::from_bytes_at(&config_page, 0).unwrap();
+    assert_eq!(layout.version_number, config_view.get_version_number());
+    assert_eq!(config_page.get_page_size(), memory.page_size());
+
+    let free_list_page =
+        memory.get_page_ref::<FreeListPageRef>(config_page.get_recycled_pages_list())?;
+    assert_eq!(free_list_page.get_recycled_pages_list()?, recycled_pages);
+    assert_eq!(free_list_page.get_free_list_page_next(), 0);
+
+    // Views are shared borrows: they can be taken twice but block exclusive access
+    let raw_page = memory.get_page_ref::<GenericPageRef>(config_page_index)?;
+    assert_eq!(raw_page.data(), config_page.data());
+    assert!(memory
+        .get_page_mut::<GenericPage>(config_page_index)
+        .is_err());
+    drop(raw_page);
+    drop(config_page);
+    drop(free_list_page);
+
+    // Reading doesn't mark pages dirty
+    assert_eq!(memory.num_dirty_pages(), 0);
+    assert_eq!(memory.num_borrowed_pages(), 0);
+
+    Ok(())
+}
+
+// Level, message and file key of a record
+type CapturedRecord = (slog::Level, String, Option<String>);
+
+// Keeps every record
+struct CaptureDrain(Arc<Mutex<Vec<CapturedRecord>>>);
+
+struct FileKey(Option<String>);
+
+impl slog::Serializer for FileKey {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if key == "file" {
+            self.0 = Some(val.to_string());
+        }
+        Ok(())
+    }
+}
+
+impl slog::Drain for CaptureDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record, values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+        let mut file = FileKey(None);
+        let _ = slog::KV::serialize(values, record, &mut file);
+        self.0
+            .lock()
+            .unwrap()
+            .push((record.level(), record.msg().to_string(), file.0));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_options_logger() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_options_logger.bin");
+    let records = Arc::new(Mutex::new(vec![]));
+    let logger = slog::Logger::root(CaptureDrain(records.clone()), slog::o!());
+    {
+        let mut memory: MemoryManager = MemoryManagerOptions::new()
+            .logger(logger)
+            .truncate(true)
+            .open(filename, 8)?;
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 8)?;
+        page_manager.growth_policy = GrowthPolicy::Doubling { max_pages: None };
+        let mut pages = page_manager.get_free_pages(10, true)?;
+        page_manager.recyle_pages(&mut pages);
+        page_manager.consolidate_state()?;
+    }
+
+    let records = records.lock().unwrap();
+    assert!(!records.is_empty());
+    // Store, memory manager and page manager records, all tagged with the file
+    assert!(records
+        .iter()
+        .any(|(_, msg, _)| msg.starts_with("File size")));
+    assert!(records
+        .iter()
+        .any(|(_, msg, _)| msg.starts_with("Resized from")));
+    assert!(records
+        .iter()
+        .any(|(_, msg, _)| msg.starts_with("Growing from")));
+    assert!(records
+        .iter()
+        .all(|(_, _, file)| file.as_deref() == Some(filename)));
+    // Allocations only log at debug level
+    assert!(records
+        .iter()
+        .filter(|(_, msg, _)| msg.starts_with("Recycling"))
+        .all(|(level, _, _)| *level == slog::Level::Debug));
+
+    Ok(())
+}
+
+fn capture_logger() -> (slog::Logger, Arc<Mutex<Vec<CapturedRecord>>>) {
+    let records = Arc::new(Mutex::new(vec![]));
+    let logger = slog::Logger::root(CaptureDrain(records.clone()), slog::o!());
+    (logger, records)
+}
+
+#[test]
+fn test_options_logger_stores() -> io::Result<()> {
+    let dir = TempDir::new();
+    let file_io = &dir.file("test_options_logger_stores_file_io.bin");
+    let segmented = &dir.file("test_options_logger_stores_segmented.bin");
+    let (logger, records) = capture_logger();
+    let logged = |msg: &str, file: Option<&str>| {
+        records
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(_, m, f)| m.starts_with(msg) && f.as_deref() == file)
+    };
+
+    let memory = MemoryManagerOptions::new()
+        .logger(logger.clone())
+        .file_io(true)
+        .open(file_io, 8)?;
+    assert_eq!(memory.num_cached_pages(), 0);
+    drop(memory);
+    assert!(logged("File size", Some(file_io)));
+
+    let memory = MemoryManagerOptions::new()
+        .logger(logger.clone())
+        .read_only(true)
+        .open(file_io, 0)?;
+    assert!(memory.is_read_only());
+    assert_eq!(memory.num_pages(), 8);
+    drop(memory);
+    assert!(logged(
+        "Correctly mapped 8 pages into memory (read-only)",
+        Some(file_io)
+    ));
+
+    let memory = MemoryManagerOptions::new()
+        .logger(logger.clone())
+        .pages_per_segment(4)
+        .open(segmented, 8)?;
+    assert_eq!(memory.num_pages(), 8);
+    drop(memory);
+    assert!(logged("Created segment", Some(segmented)));
+
+    let mut memory = MemoryManagerOptions::new()
+        .logger(logger.clone())
+        .in_memory(4)?;
+    memory.resize(8)?;
+    assert!(logged("Resized from 4 to 8 pages", None));
+
+    // The other stores have no read-only mode
+    let err = MemoryManagerOptions::new()
+        .logger(logger)
+        .read_only(true)
+        .file_io(true)
+        .open(file_io, 0)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "read_only can't be combined with truncate or file_io"
+    );
+    assert!(records.lock().unwrap().iter().any(
+        |(level, _, file)| *level == slog::Level::Critical && file.as_deref() == Some(file_io)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_set_logger() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_set_logger.bin");
+    let (logger, records) = capture_logger();
+
+    let mut memory: MemoryManager = MemoryManager::new_segmented(filename, 4, 4)?;
+    memory.set_logger(logger);
+    // Growing creates a segment, logged by the store, then the memory manager logs the resize
+    memory.resize(8)?;
+    {
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 8)?;
+        page_manager.growth_policy = GrowthPolicy::Doubling { max_pages: None };
+        page_manager.get_free_pages(10, false)?;
+    }
+    drop(memory);
+
+    let records = records.lock().unwrap();
+    for msg in ["Created segment", "Resized from", "Growing from"] {
+        assert!(records.iter().any(|(_, m, _)| m.starts_with(msg)));
+    }
+    assert!(records
+        .iter()
+        .all(|(_, _, file)| file.as_deref() == Some(filename)));
+
+    Ok(())
+}
@@ -1,57 +1,57 @@
-use memory_manager::memory_manager::MemoryManager;
-use memory_manager::pages::config_page::{ConfigPage, MemoryLayout};
-use memory_manager::pages::page_manager::PageManager;
+use memory_manager::error::MemoryManagerError;
+use memory_manager::memory_manager::{
+    MemoryManager, PageGuard, META_PAGE_INDEXES, RESERVED_CONFIG_PAGE_INDEX,
+};
+use memory_manager::pages::config_page::{
+    ConfigPage, MemoryLayout, FILE_HEADER_BYTES, FORMAT_VERSION, MAGIC,
+};
+use memory_manager::pages::free_list_page::{free_list_capacity, FreeListPage, FreeListPageRef};
+use memory_manager::pages::generic_page::GenericPage;
+use memory_manager::pages::page_manager::{CheckIssue, GrowthPolicy, PageManager};
+use memory_manager::pages::read_only_page_manager::ReadOnlyPageManager;
+use std::collections::BTreeSet;
 use std::fs;
-use std::fs::File;
-use std::io::Read;
 use std::io::{self};
-use std::os::unix::prelude::FileExt;
+
+mod common;
+use common::TempDir;
 
 #[test]
 // this test should fail, since we don't have enough pages to initialize the page manager
 fn test_page_manager_initialization_fail() -> io::Result<()> {
-    let filename = "test_page_manager_initialization_fail.bin";
     let num_pages = 1u64;
-    let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
     let page_manager = PageManager::new(&mut memory, num_pages);
     match page_manager {
-        Ok(_) => {
-            let _ = fs::remove_file(filename);
-            Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "it shouldn't possible to initialize the page manager with 1 page",
-            ))
-        }
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "it shouldn't possible to initialize the page manager with 1 page",
+        )),
         Err(e) => {
-            assert_eq!(
-                e.to_string(),
-                "Error: not enough pages! total_allocated_pages: 1, last_used_page: 1"
-            );
-            let _ = fs::remove_file(filename);
+            assert!(matches!(
+                e,
+                MemoryManagerError::OutOfPages {
+                    total_allocated_pages: 1,
+                    last_used_page: 2
+                }
+            ));
             Ok(())
         }
     }
 }
 #[test]
 fn test_page_manager_initialization() -> io::Result<()> {
-    let filename = "test_page_manager_initialization.bin";
-
-    let num_pages = 2u64;
-    let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+    let num_pages = 3u64;
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
     PageManager::new(&mut memory, num_pages).unwrap();
-    memory.flush()?;
 
-    let mut file = File::open(filename)?;
-    let mut buffer = [0u8; 4096];
-    file.read_exact(&mut buffer)?;
-
-    let config_page = ConfigPage { data: &mut buffer };
+    let config_page = memory.get_page_mut::<ConfigPage>(RESERVED_CONFIG_PAGE_INDEX)?;
 
     let expected_at_0 = MemoryLayout {
-        total_allocated_pages: 2,
+        total_allocated_pages: 3,
         version_number: 1,
-        last_used_page: 1,
-        recycled_pages_list: 1,
+        last_used_page: 2,
+        recycled_pages_list: 2,
         previous_config_page: 0,
         offset: 1,
     };
@@ -72,34 +72,31 @@ fn test_page_manager_initialization() -> io::Result<()> {
 
     assert_eq!(expected_at_1, result_at_0);
 
-    let _ = fs::remove_file(filename);
-
     Ok(())
 }
 #[test]
 fn test_page_manager_consolidate_1() -> io::Result<()> {
-    let filename = "test_page_manager_consolidate_1.bin";
-    let num_pages = 4u64;
-    let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+    let num_pages = 5u64;
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
     let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
 
     page_manager.consolidate_state()?;
+    // The second version is stored in the second meta page
+    let config_page_index = page_manager.config_page().index();
+    assert_eq!(config_page_index, META_PAGE_INDEXES[1]);
+    drop(page_manager);
 
-    let mut file = File::open(filename)?;
-    let mut buffer = [0u8; 4096];
-    file.read_exact(&mut buffer)?;
-
-    let config_page = ConfigPage { data: &mut buffer };
+    let config_page = memory.get_page_mut::<ConfigPage>(config_page_index)?;
 
     // We increment the version number by 1, because the consolidate_state method increments the version number.
-    // the last used page is 3, since we use 1 for the config page, 1 for the new free list page, and the recycled pages list is empty
-    // but stored at 3
+    // the last used page is 4, since we use 3 for the temporal config page and 4 for the new free list page,
+    // which stores 3 so it can be reused by the next commit
     // the offset it's also incremented
     let expected_at_0 = MemoryLayout {
-        total_allocated_pages: 4,
+        total_allocated_pages: 5,
         version_number: 2,
-        last_used_page: 2,
-        recycled_pages_list: 1,
+        last_used_page: 4,
+        recycled_pages_list: 4,
         previous_config_page: 0,
         offset: 2,
     };
@@ -108,10 +105,10 @@ fn test_page_manager_consolidate_1() -> io::Result<()> {
     assert_eq!(expected_at_0, result);
 
     let expected_at_1 = MemoryLayout {
-        total_allocated_pages: 4,
+        total_allocated_pages: 5,
         version_number: 1,
-        last_used_page: 1,
-        recycled_pages_list: 1,
+        last_used_page: 2,
+        recycled_pages_list: 2,
         previous_config_page: 0,
         offset: 1,
     };
@@ -120,46 +117,43 @@ fn test_page_manager_consolidate_1() -> io::Result<()> {
 
     assert_eq!(expected_at_1, result_at_0);
 
-    let _ = fs::remove_file(filename);
-
     Ok(())
 }
 
 #[test]
-fn test_page_manager_consolidate_126_1_page() -> io::Result<()> {
+fn test_page_manager_consolidate_125_1_page() -> io::Result<()> {
     let num_pages = 129u64;
-    let mut memory: MemoryManager =
-        MemoryManager::new("test_page_manager_consolidate_10_1_page.bin", num_pages).unwrap();
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
     let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
 
-    for _ in 0..126 {
+    for _ in 0..125 {
         page_manager.consolidate_state()?;
     }
+    let config_page_index = page_manager.config_page().index();
+    drop(page_manager);
 
-    let mut file = File::open("test_page_manager_consolidate_10_1_page.bin")?;
-    let mut buffer = [0u8; 4096];
-    file.read_exact(&mut buffer)?;
-
-    let config_page = ConfigPage { data: &mut buffer };
+    let config_page = memory.get_page_mut::<ConfigPage>(config_page_index)?;
 
+    // The first commit takes the temporal config page (3) and the free list page holding it
+    // (4), every other commit reuses the same temporal page and the free list doesn't change
     let expected_at_0 = MemoryLayout {
         total_allocated_pages: 129,
-        version_number: 127,
-        last_used_page: 127,
-        recycled_pages_list: 1,
+        version_number: 126,
+        last_used_page: 4,
+        recycled_pages_list: 4,
         previous_config_page: 0,
-        offset: 127,
+        offset: 126,
     };
     let result: MemoryLayout = MemoryLayout::from_bytes_at(&config_page, 0).unwrap();
 
     assert_eq!(expected_at_0, result);
 
-    for i in 2..127 {
+    for i in 2..126 {
         let expected_at_i = MemoryLayout {
             total_allocated_pages: 129,
             version_number: i,
-            last_used_page: i,
-            recycled_pages_list: 1,
+            last_used_page: 4,
+            recycled_pages_list: 4,
             previous_config_page: 0,
             offset: i,
         };
@@ -169,77 +163,914 @@ fn test_page_manager_consolidate_126_1_page() -> io::Result<()> {
         assert_eq!(expected_at_i, result_at_i);
     }
 
-    let _ = fs::remove_file("test_page_manager_consolidate_10_1_page.bin");
-
     Ok(())
 }
 
 #[test]
-fn test_page_manager_consolidate_252_1_page() -> io::Result<()> {
-    let filename = "test_page_manager_consolidate_252_1_page.bin";
+fn test_page_manager_consolidate_250_1_page() -> io::Result<()> {
     let num_pages = 259u64;
-    let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
     let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
 
-    for _ in 0..254 {
+    for _ in 0..250 {
         page_manager.consolidate_state()?;
     }
+    let config_page_index = page_manager.config_page().index();
+    drop(page_manager);
 
-    let mut file = File::open(filename)?;
-    let mut buffer = [0u8; 4096];
-    let mut buffer_prev = [0u8; 4096];
-    file.read_exact(&mut buffer)?;
-
-    let config_page: ConfigPage<'_> = ConfigPage { data: &mut buffer };
-    file.read_exact_at(
-        &mut buffer_prev,
-        4096 * config_page.get_previous_config_page(),
-    )?;
-    let config_page_prev: ConfigPage<'_> = ConfigPage {
-        data: &mut buffer_prev,
-    };
+    let config_page: PageGuard<ConfigPage<'_>> =
+        memory.get_page_mut::<ConfigPage>(config_page_index)?;
+    let config_page_prev: PageGuard<ConfigPage<'_>> =
+        memory.get_page_mut::<ConfigPage>(config_page.get_previous_config_page())?;
 
+    // Same pages as in the 125 commits test, plus the archived config page (5)
     let expected_at_0 = MemoryLayout {
         total_allocated_pages: 259,
-        version_number: 255,
-        last_used_page: 256,
-        recycled_pages_list: 1,
-        previous_config_page: 130,
-        offset: 128,
+        version_number: 251,
+        last_used_page: 5,
+        recycled_pages_list: 4,
+        previous_config_page: 5,
+        offset: 126,
     };
     let result: MemoryLayout = MemoryLayout::from_bytes_at(&config_page, 0).unwrap();
 
     assert_eq!(expected_at_0, result);
     let mut result_at_i: MemoryLayout;
-    for i in 2..254 {
+    for i in 2..251 {
         let mut expected_at_i = MemoryLayout {
             total_allocated_pages: 259,
             version_number: i,
-            last_used_page: i,
-            recycled_pages_list: 1,
+            last_used_page: 4,
+            recycled_pages_list: 4,
             previous_config_page: 0,
             offset: i,
         };
 
         println!("i: {}", i);
 
-        if i < 128 {
+        if i < 126 {
             result_at_i = MemoryLayout::from_bytes_at(&config_page_prev, i).unwrap();
         } else {
-            if expected_at_i.version_number >= 129 {
-                expected_at_i.last_used_page = expected_at_i.last_used_page + 1;
-                expected_at_i.recycled_pages_list = 1;
+            if expected_at_i.version_number >= 127 {
+                expected_at_i.last_used_page += 1;
             }
-            expected_at_i.previous_config_page = 130;
-            expected_at_i.offset = i % 127;
+            expected_at_i.previous_config_page = 5;
+            expected_at_i.offset = i - 125;
 
-            result_at_i = MemoryLayout::from_bytes_at(&config_page, i % 127).unwrap();
+            result_at_i = MemoryLayout::from_bytes_at(&config_page, i - 125).unwrap();
         }
 
         assert_eq!(expected_at_i, result_at_i);
     }
 
-    let _ = fs::remove_file(filename);
+    Ok(())
+}
+
+#[test]
+fn test_page_manager_open_existing() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_page_manager_open_existing.bin");
+    let num_pages = 8u64;
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
+        page_manager.consolidate_state()?;
+    }
+
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    assert_eq!(memory.num_pages(), num_pages);
+    let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    assert_eq!(page_manager.total_allocated_pages, num_pages);
+    assert_eq!(page_manager.config_page().get_version_number(), 2);
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * num_pages);
+
+    Ok(())
+}
+
+#[test]
+fn test_page_manager_open_size_mismatch() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_page_manager_open_size_mismatch.bin");
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, 4).unwrap();
+        PageManager::new(&mut memory, 4).unwrap();
+    }
+    // Grow the file behind the back of the config page, as a crash after growing it does: the
+    // pages past the committed size are ignored
+    fs::OpenOptions::new()
+        .write(true)
+        .open(filename)?
+        .set_len(4096 * 6)?;
+    {
+        let memory: MemoryManager = MemoryManager::open_read_only(filename)?;
+        let page_manager = ReadOnlyPageManager::open(&memory)?;
+        assert_eq!(
+            page_manager.get_memory_layout_at(0)?.total_allocated_pages,
+            4
+        );
+    }
+    {
+        let mut memory: MemoryManager = MemoryManager::open(filename)?;
+        let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+        assert_eq!(page_manager.total_allocated_pages, 4);
+        assert_eq!(page_manager.config_page().get_version_number(), 1);
+    }
+    // Opening doesn't resize the file
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * 6);
+
+    // A file missing committed pages is refused
+    fs::OpenOptions::new()
+        .write(true)
+        .open(filename)?
+        .set_len(4096 * 3)?;
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    let result = PageManager::open(&mut memory);
+
+    match result {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "it shouldn't be possible to open a database whose size doesn't match the config page",
+        )),
+        Err(e) => {
+            assert_eq!(
+                e.to_string(),
+                "Database size mismatch: total_allocated_pages: 4, pages in file: 3"
+            );
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn test_memory_manager_open_missing_file() {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_memory_manager_open_missing_file.bin");
+    let result = MemoryManager::open(filename);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(!std::path::Path::new(filename).exists());
+}
+
+#[test]
+fn test_page_manager_grows_file() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_page_manager_grows_file.bin");
+    let num_pages = 4u64;
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
+        page_manager.growth_policy = GrowthPolicy::Doubling { max_pages: None };
+
+        let pages = page_manager.get_free_pages(10, true)?;
+        assert_eq!(pages, (3..13).collect::<Vec<u64>>());
+        assert_eq!(page_manager.total_allocated_pages, 16);
+        page_manager.consolidate_state()?;
+        assert_eq!(page_manager.config_page().get_total_allocated_pages(), 16);
+    }
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * 16);
+
+    // The new size must be persisted so the database can be opened again
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    assert_eq!(page_manager.total_allocated_pages, 16);
+    assert_eq!(page_manager.last_used_page, 14);
+
+    Ok(())
+}
+
+#[test]
+fn test_page_manager_growth_cap() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_page_manager_growth_cap.bin");
+    let num_pages = 4u64;
+    let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+    let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
+    page_manager.growth_policy = GrowthPolicy::Increment {
+        pages: 2,
+        max_pages: Some(7),
+    };
+
+    page_manager.get_free_pages(3, true)?;
+    assert_eq!(page_manager.total_allocated_pages, 6);
+    page_manager.get_free_pages(1, true)?;
+    assert_eq!(page_manager.total_allocated_pages, 7);
+    let err = page_manager.get_free_pages(1, true).unwrap_err();
+    assert!(matches!(
+        err,
+        MemoryManagerError::OutOfPages {
+            total_allocated_pages: 7,
+            last_used_page: 7
+        }
+    ));
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * 7);
+
+    Ok(())
+}
+
+#[test]
+fn test_out_of_pages_keeps_state() -> io::Result<()> {
+    let mut memory: MemoryManager = MemoryManager::in_memory(10)?;
+    let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 10)?;
+    let mut pages = page_manager.get_free_pages(3, true)?;
+    page_manager.recyle_pages(&mut pages);
+    page_manager.consolidate_state()?;
+    let recycled_pages = page_manager.recycled_pages.clone();
+    let last_used_page = page_manager.last_used_page;
+
+    // Failing takes nothing: the recycled pages are kept and the next commit still fits
+    let err = page_manager.get_free_pages(20, true).unwrap_err();
+    assert!(matches!(err, MemoryManagerError::OutOfPages { .. }));
+    assert_eq!(page_manager.recycled_pages, recycled_pages);
+    assert_eq!(page_manager.last_used_page, last_used_page);
+    page_manager.consolidate_state()?;
+    page_manager.consolidate_state()?;
+    assert_eq!(page_manager.config_page().get_version_number(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_growth_policy_next_num_pages() {
+    assert_eq!(GrowthPolicy::Disabled.next_num_pages(4, 5), None);
+    let doubling = GrowthPolicy::Doubling {
+        max_pages: Some(100),
+    };
+    assert_eq!(doubling.next_num_pages(4, 5), Some(8));
+    assert_eq!(doubling.next_num_pages(4, 33), Some(64));
+    assert_eq!(doubling.next_num_pages(4, 65), Some(100));
+    assert_eq!(doubling.next_num_pages(4, 101), None);
+    let increment = GrowthPolicy::Increment {
+        pages: 10,
+        max_pages: None,
+    };
+    assert_eq!(increment.next_num_pages(4, 5), Some(14));
+    assert_eq!(increment.next_num_pages(4, 25), Some(34));
+}
+
+#[test]
+fn test_page_manager_vacuum() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_page_manager_vacuum.bin");
+    let num_pages = 20u64;
+    let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+    let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
+
+    let pages = page_manager.get_free_pages(10, true)?;
+    for &page in &pages {
+        let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        generic_page.page_mut().data_mut().fill(page as u8);
+    }
+    page_manager.recyle_pages(&mut vec![3, 4, 5, 6]);
+    page_manager.consolidate_state()?;
+
+    // Not enough pages for the spare pages asked: nothing is moved
+    assert!(page_manager
+        .vacuum(num_pages, |_, _, _| unreachable!())
+        .is_err());
+    assert_eq!(
+        page_manager.memory().get_page_mut::<GenericPage>(3)?.data()[0],
+        3
+    );
+
+    let mut relocations = vec![];
+    let released = page_manager.vacuum(2, |memory, old_page, new_page| {
+        assert_eq!(
+            memory.get_page_mut::<GenericPage>(new_page)?.data()[0],
+            old_page as u8
+        );
+        relocations.push((old_page, new_page));
+        Ok(())
+    })?;
+
+    // Pages 7..=12 are live, 2 and 14 are free list pages and 3, 4, 5, 6 and 13 are free. The
+    // free list pages are referenced by the last commit, they are kept until the vacuum is
+    // committed: page 2 goes to the new free list, held by page 6.
+    assert_eq!(relocations, vec![(10, 3), (11, 4), (12, 5)]);
+    assert_eq!(released, 8);
+    assert_eq!(page_manager.total_allocated_pages, 12);
+    assert_eq!(page_manager.last_used_page, 9);
+    assert_eq!(page_manager.get_free_list_page_at(0)?, vec![2]);
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * 12);
+
+    let expected_at_0 = MemoryLayout {
+        total_allocated_pages: 12,
+        version_number: 3,
+        last_used_page: 9,
+        recycled_pages_list: 6,
+        previous_config_page: 0,
+        offset: 1,
+    };
+    assert_eq!(
+        MemoryLayout::from_bytes_at(page_manager.config_page(), 0).unwrap(),
+        expected_at_0
+    );
+
+    // The database keeps working after the vacuum
+    page_manager.recyle_pages(&mut vec![3]);
+    page_manager.consolidate_state()?;
+    drop(page_manager);
+    drop(memory);
+
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    let mut page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    // The free list holds the page we recycled and the temporal config page of the last commit
+    assert_eq!(page_manager.get_free_list_page_at(0)?, vec![3, 2]);
+    assert_eq!(page_manager.get_free_pages(1, true)?, vec![3]);
+    assert_eq!(
+        page_manager.memory().get_page_mut::<GenericPage>(7)?.data()[0],
+        7
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_read_only_page_manager() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_read_only_page_manager.bin");
+    let num_pages = 8u64;
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, num_pages).unwrap();
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages).unwrap();
+        let mut pages = page_manager.get_free_pages(2, true)?;
+        page_manager.recyle_pages(&mut pages);
+        page_manager.consolidate_state()?;
+    }
+    let modified = fs::metadata(filename)?.modified()?;
+
+    let mut memory: MemoryManager = MemoryManager::open_read_only(filename)?;
+    assert!(memory.is_read_only());
+    {
+        let page_manager = ReadOnlyPageManager::open(&memory)?;
+        assert_eq!(page_manager.version_number, 2);
+        assert_eq!(page_manager.last_used_page, 6);
+        assert_eq!(page_manager.get_recycled_pages()?, vec![3, 4, 5]);
+        assert_eq!(page_manager.get_free_list_page_at(1)?, Vec::<u64>::new());
+        assert_eq!(
+            page_manager.get_memory_layout_at(1)?,
+            MemoryLayout {
+                total_allocated_pages: 8,
+                version_number: 1,
+                last_used_page: 2,
+                recycled_pages_list: 2,
+                previous_config_page: 0,
+                offset: 1,
+            }
+        );
+        assert!(matches!(
+            page_manager.get_memory_layout_at(3),
+            Err(MemoryManagerError::VersionOutOfRange {
+                version: 3,
+                max_version: 2
+            })
+        ));
+    }
+
+    // Writers are refused
+    assert_eq!(
+        memory.get_page_mut::<GenericPage>(1).unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    assert_eq!(
+        memory.resize(16).unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    assert!(PageManager::open(&mut memory).is_err());
+    assert_eq!(memory.get_page(0)?.data()[FILE_HEADER_BYTES], 8);
+    drop(memory);
+
+    assert_eq!(fs::metadata(filename)?.len(), 4096 * num_pages);
+    assert_eq!(fs::metadata(filename)?.modified()?, modified);
+
+    Ok(())
+}
+
+#[test]
+fn test_page_manager_page_sizes() -> io::Result<()> {
+    let dir = TempDir::new();
+    for page_size in [0x2000u64, 0x4000, 0x10000] {
+        let filename = dir.file(&format!("test_page_manager_page_size_{}.bin", page_size));
+        let num_pages = 8u64;
+        {
+            let mut memory: MemoryManager =
+                MemoryManager::new_with_page_size(&filename, num_pages, page_size)?;
+            let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+            let pages = page_manager.get_free_pages(3, true)?;
+            for &page in &pages {
+                let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+                assert_eq!(generic_page.data().len() as u64, page_size);
+                generic_page.page_mut().data_mut().fill(page as u8);
+            }
+            page_manager.recyle_pages(&mut vec![pages[1]]);
+            page_manager.consolidate_state()?;
+        }
+        assert_eq!(fs::metadata(&filename)?.len(), page_size * num_pages);
+
+        // The page size is read back from the file
+        let mut memory: MemoryManager = MemoryManager::open(&filename)?;
+        assert_eq!(memory.page_size(), page_size);
+        let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+        assert_eq!(page_manager.config_page().get_page_size(), page_size);
+        assert_eq!(
+            page_manager.config_page().get_history_slots(),
+            (page_size - 36) / 32
+        );
+        assert_eq!(page_manager.recycled_pages, vec![4, 6]);
+        let generic_page = page_manager.memory().get_page_mut::<GenericPage>(5)?;
+        assert!(generic_page.data().iter().all(|&byte| byte == 5));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_page_manager_consolidate_history_slots() -> io::Result<()> {
+    // 8K pages keep 254 versions in the config page before rolling over
+    let page_size = 0x2000u64;
+    let num_pages = 300u64;
+    let mut memory: MemoryManager = MemoryManager::in_memory_with_page_size(num_pages, page_size)?;
+    let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+
+    for _ in 0..253 {
+        page_manager.consolidate_state()?;
+    }
+    assert_eq!(page_manager.config_page().get_offset(), 254);
+    assert_eq!(page_manager.config_page().get_previous_config_page(), 0);
+    // Versions past the history stored in the page are an error, not a panic
+    assert_eq!(page_manager.config_page().get_version_number_at(253)?, 253);
+    assert!(matches!(
+        page_manager.config_page().get_version_number_at(254),
+        Err(MemoryManagerError::VersionOutOfRange {
+            version: 254,
+            max_version: 253
+        })
+    ));
+
+    page_manager.consolidate_state()?;
+    assert_eq!(page_manager.config_page().get_offset(), 2);
+    assert_ne!(page_manager.config_page().get_previous_config_page(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_page_size() {
+    for page_size in [0u64, 0x800, 0x3000, 0x400000] {
+        let result = MemoryManager::in_memory_with_page_size(4, page_size);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_read_only_open_missing_file() {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_read_only_open_missing_file.bin");
+    assert!(MemoryManager::open_read_only(filename).is_err());
+    assert!(!std::path::Path::new(filename).exists());
+}
+
+#[test]
+fn test_superblock() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_superblock.bin");
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, 4)?;
+        let page_manager: PageManager<'_> = PageManager::new(&mut memory, 4)?;
+        assert_eq!(
+            page_manager.config_page().get_format_version(),
+            FORMAT_VERSION
+        );
+        assert_eq!(page_manager.config_page().get_feature_flags(), 0);
+        assert_eq!(page_manager.config_page().get_page_size(), 4096);
+    }
+    assert_eq!(&fs::read(filename)?[4..12], &MAGIC);
+
+    // Reusing the file with another page size
+    assert_eq!(
+        MemoryManager::new_with_page_size(filename, 4, 0x2000)
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidInput
+    );
+
+    // Newer and older formats
+    let mut bytes = fs::read(filename)?;
+    for version in [3, 5] {
+        bytes[12] = version;
+        fs::write(filename, &bytes)?;
+        assert!(matches!(
+            MemoryManager::open(filename),
+            Err(MemoryManagerError::UnsupportedFormatVersion {
+                version: found,
+                supported: 4
+            }) if found == version as u64
+        ));
+    }
+
+    // Features we don't know about
+    bytes[12] = 4;
+    bytes[17] = 1;
+    fs::write(filename, &bytes)?;
+    assert!(matches!(
+        MemoryManager::open(filename),
+        Err(MemoryManagerError::UnsupportedFeatures { flags: 0x100 })
+    ));
+    bytes[17] = 0;
+    fs::write(filename, &bytes)?;
+    assert!(MemoryManager::open(filename).is_ok());
+
+    // Foreign files are refused without touching them, even full of zeros
+    for byte in [0u8, 0xab] {
+        fs::write(filename, vec![byte; 8192])?;
+        assert!(matches!(
+            MemoryManager::open(filename),
+            Err(MemoryManagerError::BadMagic { .. })
+        ));
+        assert!(matches!(
+            MemoryManager::new(filename, 4),
+            Err(MemoryManagerError::BadMagic { .. })
+        ));
+        assert_eq!(fs::read(filename)?, vec![byte; 8192]);
+    }
+
+    // PageManager::new validates it too, only a blank page 0 gets initialized
+    let mut memory: MemoryManager = MemoryManager::in_memory(4)?;
+    memory.get_page_mut::<GenericPage>(0)?.page_mut().data_mut()[4] = 1;
+    assert!(matches!(
+        PageManager::new(&mut memory, 4),
+        Err(MemoryManagerError::BadMagic { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_checksums() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_checksums.bin");
+    let recycled_pages_page;
+    let recycled_pages;
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, 8)?;
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 8)?;
+        let mut pages = page_manager.get_free_pages(2, true)?;
+        page_manager.recyle_pages(&mut pages);
+        page_manager.consolidate_state()?;
+        recycled_pages_page = page_manager.recycled_pages_page;
+        recycled_pages = page_manager.recycled_pages.clone();
+        assert!(page_manager
+            .config_page()
+            .verify_checksum(page_manager.config_page().index())
+            .is_ok());
+    }
+    let bytes = fs::read(filename)?;
+
+    // A flipped bit in the config history of both meta pages
+    let mut corrupted = bytes.clone();
+    for index in META_PAGE_INDEXES {
+        corrupted[index as usize * 4096 + FILE_HEADER_BYTES + 40] ^= 1;
+    }
+    fs::write(filename, &corrupted)?;
+    {
+        let mut memory: MemoryManager = MemoryManager::open(filename)?;
+        assert!(matches!(
+            PageManager::open(&mut memory),
+            Err(MemoryManagerError::ChecksumMismatch { page: 0, .. })
+        ));
+    }
+    {
+        let memory: MemoryManager = MemoryManager::open_read_only(filename)?;
+        let err = ReadOnlyPageManager::open(&memory).err().unwrap();
+        assert!(matches!(
+            err,
+            MemoryManagerError::ChecksumMismatch { page: 0, .. }
+        ));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    // A flipped bit in the free list page
+    let mut corrupted = bytes.clone();
+    corrupted[recycled_pages_page as usize * 4096 + 20] ^= 1;
+    fs::write(filename, &corrupted)?;
+    {
+        let mut memory: MemoryManager = MemoryManager::open(filename)?;
+        assert!(matches!(
+            PageManager::open(&mut memory),
+            Err(MemoryManagerError::ChecksumMismatch { page, .. }) if page == recycled_pages_page
+        ));
+    }
+
+    fs::write(filename, &bytes)?;
+    {
+        let mut memory: MemoryManager = MemoryManager::open(filename)?;
+        let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+        assert_eq!(page_manager.recycled_pages, recycled_pages);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_meta_pages() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_meta_pages.bin");
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, 16)?;
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, 16)?;
+        assert_eq!(page_manager.config_page().index(), META_PAGE_INDEXES[0]);
+        // Commits alternate between the two meta pages
+        for version in 2..=4u64 {
+            page_manager.consolidate_state()?;
+            assert_eq!(page_manager.config_page().get_version_number(), version);
+            assert_eq!(
+                page_manager.config_page().index(),
+                META_PAGE_INDEXES[(version as usize + 1) % 2]
+            );
+        }
+    }
+    let bytes = fs::read(filename)?;
+    {
+        let mut memory: MemoryManager = MemoryManager::open(filename)?;
+        let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+        assert_eq!(page_manager.config_page().index(), META_PAGE_INDEXES[1]);
+        assert_eq!(page_manager.config_page().get_version_number(), 4);
+    }
+
+    // A commit torn while writing the second meta page, the previous one is used instead
+    let mut torn = bytes.clone();
+    torn[4096 + 2048..8192].fill(0);
+    fs::write(filename, &torn)?;
+    {
+        let memory: MemoryManager = MemoryManager::open_read_only(filename)?;
+        let page_manager = ReadOnlyPageManager::open(&memory)?;
+        assert_eq!(page_manager.version_number, 3);
+    }
+    {
+        let mut memory: MemoryManager = MemoryManager::open(filename)?;
+        let mut page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+        assert_eq!(page_manager.config_page().index(), META_PAGE_INDEXES[0]);
+        assert_eq!(page_manager.config_page().get_version_number(), 3);
+
+        // The next commit goes to the torn page
+        page_manager.consolidate_state()?;
+        assert_eq!(page_manager.config_page().index(), META_PAGE_INDEXES[1]);
+        assert_eq!(page_manager.config_page().get_version_number(), 4);
+    }
+    {
+        let mut memory: MemoryManager = MemoryManager::open(filename)?;
+        let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+        assert_eq!(page_manager.config_page().get_version_number(), 4);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_check() -> io::Result<()> {
+    let num_pages = 32u64;
+    {
+        let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+        page_manager.get_free_pages(6, true)?;
+        page_manager.recyle_pages(&mut vec![3, 4]);
+        // Enough commits to archive the first config page
+        for _ in 0..130 {
+            page_manager.consolidate_state()?;
+        }
+        assert_ne!(page_manager.config_page().get_previous_config_page(), 0);
+
+        let report = page_manager.check()?;
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.versions_checked, 131);
+    }
+
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
+    let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+    page_manager.get_free_pages(6, true)?;
+    page_manager.recyle_pages(&mut vec![3, 4]);
+    page_manager.consolidate_state()?;
+    let report = page_manager.check()?;
+    assert!(report.is_ok(), "{:?}", report.issues);
+    assert_eq!(report.versions_checked, 2);
+    // The empty free list of the first version and the new one
+    assert_eq!(report.free_list_pages_checked, 2);
+
+    // Only the second version uses the new free list page
+    let free_list_page_index = page_manager.config_page().get_recycled_pages_list();
+    let last_used_page = page_manager.config_page().get_last_used_page();
+    {
+        let mut guard = page_manager
+            .memory()
+            .get_page_mut::<FreeListPage>(free_list_page_index)?;
+        let mut free_list_page = guard.page_mut();
+        free_list_page.set_recycled_pages_list(&[5, 5, 20, 40, free_list_page_index])?;
+        free_list_page.set_free_list_page_next(free_list_page_index)?;
+        free_list_page.update_checksum();
+    }
+    let report = page_manager.check()?;
+    assert_eq!(
+        report.issues,
+        vec![
+            CheckIssue::DuplicateFreePage {
+                version: 2,
+                page: 5
+            },
+            CheckIssue::FreePageNotUsed {
+                version: 2,
+                page: 20,
+                last_used_page,
+            },
+            CheckIssue::FreePageOutOfBounds {
+                version: 2,
+                page: 40,
+                total_allocated_pages: num_pages,
+            },
+            CheckIssue::FreeListCycle {
+                version: 2,
+                page: free_list_page_index
+            },
+            CheckIssue::FreeListPageListedAsFree {
+                version: 2,
+                page: free_list_page_index
+            },
+        ]
+    );
+
+    // A page that doesn't pass its checksum stops the walk
+    page_manager
+        .memory()
+        .get_page_mut::<FreeListPage>(free_list_page_index)?
+        .page_mut()
+        .data_mut()[100] ^= 1;
+    let report = page_manager.check()?;
+    assert!(matches!(
+        report.issues.as_slice(),
+        [CheckIssue::UnreadablePage { page, .. }] if *page == free_list_page_index
+    ));
+
+    // A config page pointing back to itself, written behind the page manager's back
+    let config_page_index = page_manager.config_page().index();
+    drop(page_manager);
+    {
+        let mut guard = memory.get_page_mut::<ConfigPage>(config_page_index)?;
+        let mut config_page = guard.page_mut();
+        config_page.set_previous_config_page(config_page_index)?;
+        config_page.update_checksum();
+    }
+    // The free list page has to be readable to open the page manager again
+    memory
+        .get_page_mut::<FreeListPage>(free_list_page_index)?
+        .page_mut()
+        .data_mut()[100] ^= 1;
+    let page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    let report = page_manager.check()?;
+    assert_eq!(
+        report.issues.last(),
+        Some(&CheckIssue::ConfigCycle {
+            page: config_page_index
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+// A free list that doesn't fit in one page: every page of the chain must stay reachable
+fn test_check_free_list_chain() -> io::Result<()> {
+    let num_pages = 2048u64;
+    let mut memory: MemoryManager = MemoryManager::in_memory(num_pages).unwrap();
+    let capacity = free_list_capacity(4096) as u64;
+    let free_pages;
+    {
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+        let mut pages = page_manager.get_free_pages(1500, true)?;
+        page_manager.recyle_pages(&mut pages);
+        page_manager.consolidate_state()?;
+
+        let report = page_manager.check()?;
+        assert!(report.is_ok(), "{:?}", report.issues);
+        // The empty free list of the first version and the 3 pages of the new one
+        assert_eq!(report.free_list_pages_checked, 4);
+        // The recycled pages and the temporal config page
+        free_pages = page_manager
+            .memory()
+            .get_page_ref::<FreeListPageRef>(page_manager.recycled_pages_page)?
+            .get_chain_free_pages();
+        assert_eq!(free_pages, 1501);
+        assert_eq!(page_manager.recycled_pages.len() as u64, capacity);
+    }
+
+    // Every free page is handed out again once opened, before any new page
+    let mut page_manager = PageManager::open(&mut memory)?;
+    let last_used_page = page_manager.last_used_page;
+    let head = page_manager.recycled_pages_page;
+    let pages = page_manager.get_free_pages(free_pages, true)?;
+    assert_eq!(page_manager.last_used_page, last_used_page);
+    assert_eq!(
+        pages.iter().collect::<BTreeSet<_>>().len() as u64,
+        free_pages
+    );
+    assert!(pages.iter().all(|&page| page <= last_used_page));
+    assert_eq!(
+        page_manager.get_free_pages(1, true)?,
+        vec![last_used_page + 1]
+    );
+
+    // A chain cut after its first page loses the pages of the others
+    {
+        let mut guard = page_manager.memory().get_page_mut::<FreeListPage>(head)?;
+        let mut free_list_page = guard.page_mut();
+        free_list_page.set_free_list_page_next(0)?;
+        free_list_page.update_checksum();
+    }
+    let report = page_manager.check()?;
+    assert_eq!(
+        report.issues,
+        vec![CheckIssue::FreeListCountMismatch {
+            version: 2,
+            page: head,
+            expected: free_pages,
+            found: capacity,
+        }]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_repair() -> io::Result<()> {
+    let dir = TempDir::new();
+    let filename = &dir.file("test_repair.bin");
+    let num_pages = 32u64;
+    let live_pages = [3, 6, 7, 8, 9, 10];
+    {
+        let mut memory: MemoryManager = MemoryManager::new(filename, num_pages)?;
+        let mut page_manager: PageManager<'_> = PageManager::new(&mut memory, num_pages)?;
+        for page in page_manager.get_free_pages(8, true)? {
+            let mut generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+            generic_page.page_mut().data_mut().fill(page as u8);
+        }
+        page_manager.recyle_pages(&mut vec![4, 5]);
+        page_manager.consolidate_state()?;
+        // The free list holds 4, 5 and the temporal config page (11) in page 12
+        assert_eq!(page_manager.get_free_list_page_at(0)?, vec![4, 5, 11]);
+        assert_eq!(page_manager.recycled_pages_page, 12);
+        assert_eq!(page_manager.last_used_page, 12);
+
+        // Damage the free list page
+        page_manager
+            .memory()
+            .get_page_mut::<FreeListPage>(12)?
+            .page_mut()
+            .data_mut()[20] ^= 1;
+        assert!(matches!(
+            page_manager.check()?.issues.as_slice(),
+            [CheckIssue::UnreadablePage { page: 12, .. }]
+        ));
+
+        // Pages past the end can't be reachable
+        assert_eq!(
+            page_manager
+                .repair(|_, mark| {
+                    mark(num_pages);
+                    Ok(())
+                })
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let free_pages = page_manager.repair(|_, mark| {
+            live_pages.into_iter().for_each(mark);
+            Ok(())
+        })?;
+        // 4 holds the new root while it's built and 5 the new free list
+        assert_eq!(free_pages, 2);
+        assert_eq!(page_manager.recycled_pages_page, 5);
+        assert_eq!(page_manager.recycled_pages, vec![4, 11]);
+        assert_eq!(page_manager.config_page().get_version_number(), 3);
+        // The second version is still in the history, with its damaged free list
+        assert!(matches!(
+            page_manager.check()?.issues.as_slice(),
+            [CheckIssue::UnreadablePage { page: 12, .. }]
+        ));
+
+        // The repaired free list is used from now on
+        assert_eq!(page_manager.get_free_pages(1, true)?, vec![4]);
+        page_manager.consolidate_state()?;
+    }
+
+    let mut memory: MemoryManager = MemoryManager::open(filename)?;
+    let mut page_manager: PageManager<'_> = PageManager::open(&mut memory)?;
+    assert_eq!(page_manager.config_page().get_version_number(), 4);
+    for page in live_pages {
+        let generic_page = page_manager.memory().get_page_mut::<GenericPage>(page)?;
+        assert!(generic_page.data().iter().all(|&byte| byte == page as u8));
+    }
+
+    // Vacuum starts a fresh history, leaving the damaged page behind
+    page_manager.vacuum(4, |_, _, _| Ok(()))?;
+    let report = page_manager.check()?;
+    assert!(report.is_ok(), "{:?}", report.issues);
 
     Ok(())
 }
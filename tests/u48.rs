@@ -0,0 +1,81 @@
+use memory_manager::error::MemoryManagerError;
+use memory_manager::pages::config_page::ConfigPage;
+use memory_manager::pages::free_list_page::{free_list_capacity, FreeListPage};
+use memory_manager::pages::from_slice::FromSlice;
+use memory_manager::u48::{U16, U24, U32, U40, U48, U64, U8};
+
+#[test]
+fn test_u48_conversions() -> Result<(), MemoryManagerError> {
+    assert_eq!(U48::MAX, (1 << 48) - 1);
+    assert_eq!(U40::MAX, (1 << 40) - 1);
+    assert_eq!(U24::MAX, (1 << 24) - 1);
+    assert_eq!(U8::MAX, u8::MAX as u64);
+    assert_eq!(U16::MAX, u16::MAX as u64);
+    assert_eq!(U32::MAX, u32::MAX as u64);
+    assert_eq!(U64::MAX, u64::MAX);
+    assert_eq!(U64::BYTES, 8);
+
+    assert_eq!(u64::from(U48::try_from(U48::MAX)?), U48::MAX);
+    assert!(matches!(
+        U48::try_from(1 << 48),
+        Err(MemoryManagerError::Overflow {
+            value: 0x1_0000_0000_0000,
+            bytes: 6
+        })
+    ));
+    assert!(U40::try_from(1 << 40).is_err());
+    assert!(U24::try_from(1 << 24).is_err());
+    assert_eq!(U24::try_from(0)?, U24::ZERO);
+
+    let value = U48::try_from(0x0605_0403_0201)?;
+    assert_eq!(value.to_le_bytes(), [1, 2, 3, 4, 5, 6]);
+    assert_eq!(U48::from_le_bytes([1, 2, 3, 4, 5, 6]), value);
+    let mut bytes = [0xFFu8; 8];
+    value.write(&mut bytes);
+    assert_eq!(bytes, [1, 2, 3, 4, 5, 6, 0xFF, 0xFF]);
+    assert_eq!(U48::read(&bytes), value);
+    assert_eq!(U40::read(&bytes).get(), 0x05_0403_0201);
+    assert_eq!(U24::read(&bytes).get(), 0x03_0201);
+    assert_eq!(U16::read(&bytes).get(), 0x0201);
+    assert!(U8::try_from(256).is_err());
+
+    let value = U64::try_from(u64::MAX - 1)?;
+    assert_eq!(U64::from_le_bytes(value.to_le_bytes()), value);
+    U32::try_from(0x0403_0201)?.write(&mut bytes);
+    assert_eq!(bytes[..4], [1, 2, 3, 4]);
+
+    Ok(())
+}
+
+#[test]
+fn test_setters_reject_overflow() -> Result<(), MemoryManagerError> {
+    let mut data = vec![0u8; 4096];
+    let mut config_page = ConfigPage::from_slice(&mut data);
+    config_page.set_last_used_page(U48::MAX)?;
+    assert_eq!(config_page.get_last_used_page(), U48::MAX);
+    // The value used to be truncated, now the field keeps its previous value
+    assert!(config_page.set_last_used_page(U48::MAX + 2).is_err());
+    assert_eq!(config_page.get_last_used_page(), U48::MAX);
+    assert!(config_page.set_version_number(1 << 40).is_err());
+    assert!(config_page.set_offset(1 << 24).is_err());
+    assert_eq!(config_page.get_version_number(), 0);
+    assert_eq!(config_page.get_offset(), 0);
+
+    let mut data = vec![0u8; 4096];
+    let mut free_list_page = FreeListPage::from_slice(&mut data);
+    assert_eq!(free_list_page.get_capacity(), free_list_capacity(4096));
+    assert_eq!(free_list_capacity(4096), 679);
+    assert!(free_list_page.set_free_list_page_next(1 << 48).is_err());
+    free_list_page.set_recycled_pages_list(&[3, U48::MAX, 7])?;
+    // A page that doesn't fit leaves the page as it was
+    assert!(free_list_page
+        .set_recycled_pages_list(&[4, U48::MAX + 1])
+        .is_err());
+    assert_eq!(free_list_page.get_recycled_pages_list()?, [3, U48::MAX, 7]);
+    let too_many = vec![1; free_list_capacity(4096) + 1];
+    assert!(free_list_page.set_recycled_pages_list(&too_many).is_err());
+    free_list_page.set_recycled_pages_list(&[5])?;
+    assert_eq!(free_list_page.get_recycled_pages_list()?, [5]);
+
+    Ok(())
+}